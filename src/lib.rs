@@ -1,691 +1,7066 @@
 pub mod api {
+    use crate::utils::{
+        categories::categorize,
+        conversions::{dimension, is_known_unit, rounding_step, to_system},
+        models::{QuantityStyle, UnitSystem},
+    };
+    use log::{debug, error, warn};
     pub use models::Recipe;
-    use models::{Component, IncompatibleComponentError, RecipeList};
-    use reqwest::header::{ACCEPT, ACCEPT_ENCODING, HOST, USER_AGENT};
+    use models::{
+        ApiError, Component, Measurement, RecipeList, ShoppingItem, ShoppingList,
+        ShoppingMeasurement,
+    };
+    use reqwest::{
+        header::{ACCEPT, ACCEPT_ENCODING, HOST, RETRY_AFTER, USER_AGENT},
+        StatusCode,
+    };
+    use std::{
+        collections::{HashMap, HashSet},
+        future::Future,
+        path::PathBuf,
+        time::Duration,
+    };
+    use tokio::time::{sleep, timeout};
 
     const BASE_URL: &str = "https://tasty.p.rapidapi.com";
+    const DEFAULT_MAX_RETRIES: u32 = 3;
+    const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+    const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+    // The most recipes Tasty returns per request
+    pub const MAX_PAGE_SIZE: i64 = 40;
+    // How far a rounded amount may be from the exact one, as a fraction of the exact one
+    pub const DEFAULT_ROUNDING_TOLERANCE: f64 = 0.15;
+
+    pub fn clamp_page_size(size: i64) -> i64 {
+        size.clamp(1, MAX_PAGE_SIZE)
+    }
+
+    // Where plans get their recipes from, so they can be made without the network
+    pub trait RecipeSource {
+        fn list(
+            &self,
+            offset: i64,
+            size: i64,
+        ) -> impl Future<Output = Result<Vec<Recipe>, ApiError>> + Send;
+
+        // `None` when there's no recipe with that id
+        fn get(&self, id: i64) -> impl Future<Output = Result<Option<Recipe>, ApiError>> + Send;
+    }
+
+    pub struct TastyApi {
+        key: String,
+        timeout: Duration,
+        // Where raw response bodies are saved before they are parsed
+        dump_dir: Option<PathBuf>,
+    }
+
+    impl TastyApi {
+        pub fn new(key: impl Into<String>) -> Self {
+            TastyApi {
+                key: key.into(),
+                timeout: DEFAULT_TIMEOUT,
+                dump_dir: None,
+            }
+        }
+
+        pub fn with_timeout(mut self, timeout: Duration) -> Self {
+            self.timeout = timeout;
+            self
+        }
+
+        // Saves every response as `tasty-response-<offset>.json` in `dir`, so a response that
+        // fails to parse can be looked at
+        pub fn with_response_dump(mut self, dir: impl Into<PathBuf>) -> Self {
+            self.dump_dir = Some(dir.into());
+            self
+        }
+
+        // Returns where the response was saved, if it was
+        fn dump_response(&self, file_name: String, body: &str) -> Option<PathBuf> {
+            let path = self.dump_dir.as_ref()?.join(file_name);
+            debug!("Raw API response: {}", body);
+            if let Err(e) = std::fs::write(&path, body) {
+                warn!(
+                    "Failed to save the API response to {}: {}",
+                    path.display(),
+                    e
+                );
+            }
+
+            Some(path)
+        }
+    }
+
+    impl RecipeSource for TastyApi {
+        async fn list(&self, offset: i64, size: i64) -> Result<Vec<Recipe>, ApiError> {
+            let body = get_recipes_body_with_timeout(offset, size, &self.key, self.timeout).await?;
+            let dumped = self.dump_response(format!("tasty-response-{}.json", offset), &body);
+
+            let (recipes, count) = parse_recipe_list_with_count(&body).inspect_err(|_| {
+                if let Some(path) = &dumped {
+                    error!("The response that failed to parse is in {}", path.display());
+                }
+            })?;
+            warn_if_catalog_exhausted(offset, recipes.len(), count);
+
+            Ok(recipes)
+        }
+
+        async fn get(&self, id: i64) -> Result<Option<Recipe>, ApiError> {
+            let Some(body) = get_recipe_body_with_timeout(id, &self.key, self.timeout).await?
+            else {
+                return Ok(None);
+            };
+            let dumped = self.dump_response(format!("tasty-recipe-{}.json", id), &body);
+
+            parse_recipe(&body)
+                .inspect_err(|_| {
+                    if let Some(path) = &dumped {
+                        error!("The response that failed to parse is in {}", path.display());
+                    }
+                })
+                .map(Some)
+        }
+    }
+
+    // Pages through a fixed list of recipes, the way the API pages through its catalog, so
+    // tests never touch the network
+    #[cfg(test)]
+    pub struct MockSource {
+        pub recipes: Vec<Recipe>,
+    }
+
+    #[cfg(test)]
+    impl RecipeSource for MockSource {
+        async fn list(&self, offset: i64, size: i64) -> Result<Vec<Recipe>, ApiError> {
+            Ok(self
+                .recipes
+                .iter()
+                .skip(offset.max(0) as usize)
+                .take(clamp_page_size(size) as usize)
+                .cloned()
+                .collect())
+        }
+
+        async fn get(&self, id: i64) -> Result<Option<Recipe>, ApiError> {
+            Ok(self.recipes.iter().find(|recipe| recipe.id == id).cloned())
+        }
+    }
 
     pub async fn get_recipes_list(
         offset: i64,
         size: i64,
         rapidapi_key: &str,
-    ) -> Result<Vec<Recipe>, reqwest::Error> {
-        let client = reqwest::Client::new();
-        let response = client
-            .get(BASE_URL.to_owned() + "/recipes/list")
-            .header("X-RAPIDAPI-KEY", rapidapi_key)
-            .header("X-RAPIDAPI-HOST", "tasty.p.rapidapi.com")
-            .header(USER_AGENT, "rust reqwest client")
-            .header(ACCEPT, "*/*")
-            .header(ACCEPT_ENCODING, "gzip, deflate")
-            .header(HOST, "tasty.p.rapidapi.com")
-            .query(&[("from", offset), ("size", size)])
-            .send()
-            .await?
-            .json::<RecipeList>()
-            .await;
+    ) -> Result<Vec<Recipe>, ApiError> {
+        get_recipes_list_with_timeout(offset, size, rapidapi_key, DEFAULT_TIMEOUT).await
+    }
 
-        match response {
-            Ok(recipe_list) => Ok(recipe_list.results),
-            Err(e) => {
-                eprintln!("Failed to parse the API response!");
-                Err(e)
-            }
+    // `duration` covers every attempt, including the waits between retries
+    pub async fn get_recipes_list_with_timeout(
+        offset: i64,
+        size: i64,
+        rapidapi_key: &str,
+        duration: Duration,
+    ) -> Result<Vec<Recipe>, ApiError> {
+        parse_recipe_list(
+            &get_recipes_body_with_timeout(offset, size, rapidapi_key, duration).await?,
+        )
+    }
+
+    pub async fn get_recipe_by_id(id: i64, rapidapi_key: &str) -> Result<Option<Recipe>, ApiError> {
+        match get_recipe_body_with_timeout(id, rapidapi_key, DEFAULT_TIMEOUT).await? {
+            Some(body) => parse_recipe(&body).map(Some),
+            None => Ok(None),
         }
     }
 
-    pub fn get_components(recipes: &Vec<Recipe>) -> Vec<Component> {
-        let mut ret: Vec<Component> = Vec::new();
+    // `None` when Tasty has no recipe with that id
+    async fn get_recipe_body_with_timeout(
+        id: i64,
+        rapidapi_key: &str,
+        duration: Duration,
+    ) -> Result<Option<String>, ApiError> {
+        let query = [("id", id)];
+        let request = get_body_with_retries(
+            BASE_URL,
+            "/recipes/get-more-info",
+            &query,
+            rapidapi_key,
+            DEFAULT_MAX_RETRIES,
+        );
 
-        for recipe in recipes {
-            for section in &recipe.sections {
-                for component in &section.components {
-                    ret.push(component.clone());
-                }
+        match timeout(duration, request).await {
+            Ok(Err(ApiError::Http(StatusCode::NOT_FOUND))) => Ok(None),
+            Ok(result) => result.map(Some),
+            Err(_) => Err(ApiError::Timeout(duration)),
+        }
+    }
+
+    async fn get_recipes_body_with_timeout(
+        offset: i64,
+        size: i64,
+        rapidapi_key: &str,
+        duration: Duration,
+    ) -> Result<String, ApiError> {
+        let request =
+            get_recipes_body_with_retries(offset, size, rapidapi_key, DEFAULT_MAX_RETRIES);
+
+        match timeout(duration, request).await {
+            Ok(result) => result,
+            Err(_) => Err(ApiError::Timeout(duration)),
+        }
+    }
+
+    // Also returns how many recipes the whole catalog has, so callers can tell when `offset`
+    // has run past the end of it
+    pub async fn get_recipes_list_with_count(
+        offset: i64,
+        size: i64,
+        rapidapi_key: &str,
+    ) -> Result<(Vec<Recipe>, i32), ApiError> {
+        parse_recipe_list_with_count(
+            &get_recipes_body_with_timeout(offset, size, rapidapi_key, DEFAULT_TIMEOUT).await?,
+        )
+    }
+
+    fn warn_if_catalog_exhausted(offset: i64, returned: usize, count: i32) {
+        if offset + returned as i64 >= i64::from(count) {
+            warn!(
+                "Reached the end of Tasty's {} recipes at offset {}, use --reset-offset to start over",
+                count, offset
+            );
+        }
+    }
+
+    pub async fn get_recipes_list_with_retries(
+        offset: i64,
+        size: i64,
+        rapidapi_key: &str,
+        max_retries: u32,
+    ) -> Result<Vec<Recipe>, ApiError> {
+        parse_recipe_list(
+            &get_recipes_body_with_retries(offset, size, rapidapi_key, max_retries).await?,
+        )
+    }
+
+    pub fn parse_recipe_list(body: &str) -> Result<Vec<Recipe>, ApiError> {
+        parse_recipe_list_with_count(body).map(|(recipes, _)| recipes)
+    }
+
+    pub fn parse_recipe(body: &str) -> Result<Recipe, ApiError> {
+        serde_json::from_str::<Recipe>(body).map_err(|e| {
+            error!("Failed to parse the API response!");
+            debug!("Parse error: {:?}", e);
+            e.into()
+        })
+    }
+
+    pub fn parse_recipe_list_with_count(body: &str) -> Result<(Vec<Recipe>, i32), ApiError> {
+        match serde_json::from_str::<RecipeList>(body) {
+            Ok(recipe_list) => Ok((recipe_list.results, recipe_list.count)),
+            Err(e) => {
+                error!("Failed to parse the API response!");
+                debug!("Parse error: {:?}", e);
+                Err(e.into())
             }
         }
+    }
 
-        ret
+    async fn get_recipes_body_with_retries(
+        offset: i64,
+        size: i64,
+        rapidapi_key: &str,
+        max_retries: u32,
+    ) -> Result<String, ApiError> {
+        if size != clamp_page_size(size) {
+            debug!(
+                "Clamping the page size {} to {}",
+                size,
+                clamp_page_size(size)
+            );
+        }
+        let size = clamp_page_size(size);
+
+        get_body_with_retries(
+            BASE_URL,
+            "/recipes/list",
+            &[("from", offset), ("size", size)],
+            rapidapi_key,
+            max_retries,
+        )
+        .await
     }
 
-    pub fn make_shopping_list(
-        components: Vec<Component>,
-    ) -> Result<String, IncompatibleComponentError> {
-        let mut combined_components: Vec<Component> = Vec::new();
-        let mut ingredient_ids: Vec<i64> = Vec::new();
+    async fn get_body_with_retries(
+        base_url: &str,
+        path: &str,
+        query: &[(&str, i64)],
+        rapidapi_key: &str,
+        max_retries: u32,
+    ) -> Result<String, ApiError> {
+        let client = reqwest::Client::new();
+        let mut attempt: u32 = 0;
 
-        for component in components {
-            if ingredient_ids.contains(&component.ingredient.id) {
-                for (i, component_) in combined_components.clone().into_iter().enumerate() {
-                    if component.ingredient.id != component_.ingredient.id {
-                        continue;
-                    }
+        loop {
+            let result = client
+                .get(base_url.to_owned() + path)
+                .header("X-RAPIDAPI-KEY", rapidapi_key)
+                .header("X-RAPIDAPI-HOST", "tasty.p.rapidapi.com")
+                .header(USER_AGENT, "rust reqwest client")
+                .header(ACCEPT, "*/*")
+                .header(ACCEPT_ENCODING, "gzip, deflate")
+                .header(HOST, "tasty.p.rapidapi.com")
+                .query(query)
+                .send()
+                .await;
 
-                    combined_components[i] = (component_ + component.clone())?;
-                    break;
+            // `Some` when the request is worth retrying, with the Retry-After delay if one was sent
+            let retry: Option<Option<Duration>> = match &result {
+                Ok(response)
+                    if response.status() == StatusCode::TOO_MANY_REQUESTS
+                        || response.status() == StatusCode::SERVICE_UNAVAILABLE =>
+                {
+                    Some(retry_after(response))
                 }
-            } else {
-                ingredient_ids.push(component.ingredient.id);
-                combined_components.push(component);
+                Err(e) if e.is_connect() || e.is_timeout() => Some(None),
+                _ => None,
+            };
+
+            if let (Some(wait), true) = (retry, attempt < max_retries) {
+                let wait = wait.unwrap_or(INITIAL_BACKOFF * 2_u32.pow(attempt));
+                debug!("Retrying the request to {} in {:?}", path, wait);
+                sleep(wait).await;
+                attempt += 1;
+                continue;
             }
+
+            let response = result?;
+
+            if let Some(e) = status_error(response.status(), retry_after(&response)) {
+                return Err(e);
+            }
+
+            return Ok(response.text().await?);
         }
+    }
 
-        let mut shopping_list: Vec<String> = Vec::new();
+    // `None` for successful responses
+    pub fn status_error(status: StatusCode, retry_after: Option<Duration>) -> Option<ApiError> {
+        match status {
+            _ if status.is_success() => None,
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => Some(ApiError::Unauthorized),
+            StatusCode::TOO_MANY_REQUESTS => Some(ApiError::RateLimited { retry_after }),
+            _ => Some(ApiError::Http(status)),
+        }
+    }
 
-        for component in combined_components {
-            if component.measurements.len() == 0
-                || component
-                    .measurements
-                    .clone()
-                    .into_iter()
-                    .all(|m| m.quantity == 0.0)
-            {
-                shopping_list.push(component.ingredient.display_singular);
-            } else {
-                let quantity_str = if component.measurements[0].quantity.fract() == 0.0 {
-                    format!("{}", component.measurements[0].quantity as i64)
-                } else {
-                    format!("{:.2}", component.measurements[0].quantity)
-                };
+    fn retry_after(response: &reqwest::Response) -> Option<Duration> {
+        response
+            .headers()
+            .get(RETRY_AFTER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.trim().parse::<u64>().ok())
+            .map(Duration::from_secs)
+    }
 
-                let formatted_str = format!(
-                    "{}: {} {}",
-                    component.ingredient.display_singular,
-                    quantity_str,
-                    component.measurements[0].unit.abbreviation
-                );
+    pub fn get_components(recipes: &[Recipe]) -> Vec<Component> {
+        let mut ret: Vec<Component> = Vec::new();
 
-                shopping_list.push(formatted_str);
+        for recipe in recipes {
+            for section in &recipe.sections {
+                for component in &section.components {
+                    let mut component = component.clone();
+                    component.normalize();
+                    ret.push(component);
+                }
             }
         }
 
-        Ok(shopping_list.join("\n"))
+        ret
     }
 
-    pub mod models {
-        use std::ops::Add;
+    // Like `get_components`, but moves the components out instead of cloning them
+    pub fn take_components(recipes: Vec<Recipe>) -> Vec<Component> {
+        recipes
+            .into_iter()
+            .flat_map(|recipe| recipe.sections)
+            .flat_map(|section| section.components)
+            .map(|mut component| {
+                component.normalize();
+                component
+            })
+            .collect()
+    }
 
-        use thiserror::Error;
+    // Distinct ingredients with an amount that would make it onto the shopping list
+    pub fn usable_ingredient_count(recipe: &Recipe) -> usize {
+        recipe
+            .sections
+            .iter()
+            .flat_map(|section| &section.components)
+            .filter(|component| {
+                component
+                    .measurements
+                    .iter()
+                    .any(|m| m.is_valid() && m.quantity != 0.0)
+            })
+            .map(|component| component.ingredient.id)
+            .collect::<HashSet<_>>()
+            .len()
+    }
 
-        use crate::utils::numeric;
-        use serde::{de, Deserialize, Deserializer};
+    pub fn scale_component(component: &Component, factor: f64) -> Component {
+        let mut scaled = component.clone();
 
-        #[derive(Deserialize, Debug, Clone)]
-        pub struct Unit {
-            name: String,
-            pub abbreviation: String,
+        for measurement in &mut scaled.measurements {
+            measurement.quantity *= factor;
         }
 
-        fn parse_float<'de, D>(deserializer: D) -> Result<f64, D::Error>
-        where
-            D: Deserializer<'de>,
-        {
-            let numeric_str = String::deserialize(deserializer)?;
-            let n_chars = numeric_str.split_whitespace().count();
-            let parsed: Result<f64, _> = numeric_str.parse();
+        scaled
+    }
 
-            if numeric_str.is_ascii() && parsed.is_ok() {
-                // Normal number
-                return Ok(parsed.unwrap());
-            } else if n_chars > 1 && n_chars < 3 {
-                // Mixed fraction
-                let mut split = numeric_str.split_whitespace();
+    // Snaps the quantity to the nearest amount that is practical to measure in its unit
+    pub fn round_measurement(m: &Measurement) -> Measurement {
+        round_measurement_within(m, DEFAULT_ROUNDING_TOLERANCE)
+    }
 
-                let number_part: f64 = split.next().unwrap().parse().unwrap();
-                let fraction_part: f64 =
-                    numeric(&split.next().unwrap().chars().next().unwrap()).unwrap();
+    // Quantities that would move by more than `tolerance` of themselves are left exact, so a
+    // pinch of something doesn't turn into a whole egg
+    pub fn round_measurement_within(m: &Measurement, tolerance: f64) -> Measurement {
+        let mut rounded = m.clone();
 
-                debug_assert!(split.next().is_none());
+        let Some(step) = rounding_step(&m.unit) else {
+            return rounded;
+        };
+        let snapped = ((m.quantity / step).round() * step).max(step);
 
-                return Ok(number_part + fraction_part);
-            } else if n_chars == 1 {
-                numeric(&numeric_str.chars().next().unwrap())
-                    .ok_or(de::Error::custom("Not a fraction"))
-            } else {
-                panic!("BIG PROBLEM: {}", numeric_str);
-            }
+        if (snapped - m.quantity).abs() <= m.quantity.abs() * tolerance {
+            rounded.quantity = snapped;
         }
 
-        #[derive(Deserialize, Debug, Clone)]
-        pub struct Measurement {
-            id: i64,
-            #[serde(deserialize_with = "parse_float")]
-            pub quantity: f64,
-            pub unit: Unit,
-        }
+        rounded
+    }
 
-        #[derive(Deserialize, Debug, Clone)]
-        pub struct Ingredient {
-            pub id: i64,
-            pub display_singular: String,
-        }
+    // `rounding` is the tolerance for rounding to practical amounts, or `None` to keep them exact
+    pub fn build_shopping_list(
+        components: Vec<Component>,
+        exclude_ingredient_ids: &HashSet<i64>,
+        system: UnitSystem,
+        rounding: Option<f64>,
+    ) -> ShoppingList {
+        // Each ingredient's total. Amounts only stay apart when none of their units can be
+        // added together.
+        let mut totals: HashMap<i64, Component> = HashMap::new();
+        // Ingredient ids in the order they were first seen
+        let mut ingredient_ids: Vec<i64> = Vec::new();
 
-        #[derive(Deserialize, Debug, Clone)]
-        pub struct Component {
-            pub ingredient: Ingredient,
-            pub measurements: Vec<Measurement>,
+        for component in components
+            .into_iter()
+            .filter(|component| !exclude_ingredient_ids.contains(&component.ingredient.id))
+        {
+            let id = component.ingredient.id;
+            let total = match totals.remove(&id) {
+                Some(total) => (total + component).expect("both are amounts of one ingredient"),
+                None => {
+                    ingredient_ids.push(id);
+                    component
+                }
+            };
+
+            totals.insert(id, total);
         }
 
-        #[derive(Clone, Debug, Eq, Error, PartialEq)]
-        #[error("Components must have the same ingredients in order to add their amounts.")]
-        pub struct IncompatibleComponentError;
+        let mut warnings: Vec<String> = Vec::new();
+        let mut unquantified: usize = 0;
+        let mut unknown_units: Vec<String> = Vec::new();
+        let mut unmerged: Vec<String> = Vec::new();
 
-        impl Add for Component {
-            type Output = Result<Self, IncompatibleComponentError>;
+        let mut items: Vec<ShoppingItem> = ingredient_ids
+            .into_iter()
+            .filter_map(|id| totals.remove(&id))
+            .map(|total| {
+                let ingredient = total.ingredient;
+                let name = ingredient.display_singular;
+                let mut item_unknown_units: Vec<String> = Vec::new();
+                let measurements: Vec<ShoppingMeasurement> = std::iter::once(total.measurements)
+                    .chain(total.separate)
+                    .filter_map(|amount| {
+                        let mut seen_dimensions = Vec::new();
+                        let mut seen_units: Vec<String> = Vec::new();
 
-            fn add(self, rhs: Self) -> Self::Output {
-                if self.ingredient.id != rhs.ingredient.id {
-                    return Err(IncompatibleComponentError);
-                }
+                        let mut units = amount
+                            .into_iter()
+                            .map(|mut m| {
+                                if let Some((quantity, unit)) =
+                                    to_system(m.quantity, &m.unit, system)
+                                {
+                                    m.quantity = quantity;
+                                    m.unit = unit;
+                                }
 
-                let mut ret = Component {
-                    ingredient: self.ingredient,
-                    measurements: Vec::new(),
-                };
+                                match rounding {
+                                    Some(tolerance) => round_measurement_within(&m, tolerance),
+                                    None => m,
+                                }
+                            })
+                            .filter(|m| {
+                                if !m.is_valid() {
+                                    warn!(
+                                        "Skipping an invalid quantity for {}: {}",
+                                        name, m.quantity
+                                    );
+                                    warnings
+                                        .push(format!("Skipped an invalid quantity for {}", name));
+                                }
 
-                for measurement in &self.measurements {
-                    for rhs_measurement in &rhs.measurements {
-                        if measurement.unit.name != rhs_measurement.unit.name {
-                            continue;
-                        }
+                                m.is_valid() && m.quantity != 0.0
+                            })
+                            // Converting can turn the cups and milliliters of one amount into
+                            // milliliters twice, so only its first unit of each dimension is kept
+                            .filter(|m| match dimension(&m.unit) {
+                                Some(d) if seen_dimensions.contains(&d) => false,
+                                Some(d) => {
+                                    seen_dimensions.push(d);
+                                    true
+                                }
+                                None if seen_units.contains(&m.unit.name) => false,
+                                None => {
+                                    seen_units.push(m.unit.name.clone());
+                                    true
+                                }
+                            })
+                            .inspect(|m| {
+                                if !is_known_unit(&m.unit.name) {
+                                    item_unknown_units.push(m.unit.name.clone());
+                                }
+                            })
+                            .map(|m| ShoppingMeasurement {
+                                quantity: m.quantity,
+                                unit: m.unit.abbreviation,
+                                unit_name: m.unit.name,
+                                alternates: Vec::new(),
+                            });
 
-                        ret.measurements.push(Measurement {
-                            id: measurement.id,
-                            quantity: measurement.quantity + rhs_measurement.quantity,
-                            unit: measurement.unit.clone(),
-                        })
+                        // The first unit is the one the recipe wrote the amount in
+                        let mut primary = units.next()?;
+                        primary.alternates = units.collect();
+                        Some(primary)
+                    })
+                    .collect();
+
+                // Separate amounts are ones from different recipes that no unit could add up, either
+                // because a unit wasn't recognized or because they measure different things
+                match measurements.len() {
+                    0 => unquantified += 1,
+                    1 => {}
+                    _ if !item_unknown_units.is_empty() => {
+                        for unit in item_unknown_units {
+                            if !unknown_units.contains(&unit) {
+                                unknown_units.push(unit);
+                            }
+                        }
                     }
+                    _ => unmerged.push(name.clone()),
                 }
 
-                Ok(ret)
-            }
-        }
-
-        #[derive(Deserialize, Debug)]
-        pub struct Section {
-            pub components: Vec<Component>,
-        }
+                ShoppingItem {
+                    ingredient_id: ingredient.id,
+                    category: categorize(&name),
+                    measurements,
+                    ingredient: name,
+                }
+            })
+            .collect();
 
-        #[derive(Deserialize, Debug)]
-        pub struct Tag {
-            pub id: i64,
+        if unquantified > 0 {
+            warnings.push(format!(
+                "{} ingredients had unquantified amounts",
+                unquantified
+            ));
         }
 
-        #[derive(Deserialize, Debug)]
-        pub struct Recipe {
-            pub name: String,
-            pub id: i64,
-            pub slug: String,
-            pub sections: Vec<Section>,
-            pub tags: Vec<Tag>,
+        if !unknown_units.is_empty() {
+            warnings.push(format!(
+                "Amounts in these units weren't recognized, so they couldn't be added up: {}",
+                unknown_units.join(", ")
+            ));
         }
 
-        #[derive(Deserialize, Debug)]
-        pub struct RecipeList {
-            pub count: i32,
-            pub results: Vec<Recipe>,
+        if !unmerged.is_empty() {
+            warnings.push(format!(
+                "Amounts in units that can't be added together were listed separately for {}",
+                unmerged.join(", ")
+            ));
         }
-    }
-}
 
-pub mod utils {
-    use crate::api;
-    use crate::database::{get_recipe_tags, recipe_exists};
-    use phf::phf_map;
-    use sqlx::SqlitePool;
-    use std::process::Command;
-    use text_io::try_read;
-
-    static NUMERIC: phf::Map<char, f64> = phf_map! {
-        '¼' => 0.25,
-        '½' => 0.5,
-        '¾' => 0.75,
-        '⅐' => 1_f64 / 7_f64,
-        '⅑' => 1_f64 / 9_f64,
-        '⅒' => 0.1,
-        '⅓' => 1_f64 / 3_f64,
-        '⅔' => 2_f64 / 3_f64,
-        '⅕' => 0.2,
-        '⅖' => 0.4,
-        '⅗' => 0.6,
-        '⅘' => 0.8,
-        '⅙' => 1_f64 / 6_f64,
-        '⅚' => 5_f64 / 6_f64,
-        '⅛' => 0.125,
-        '⅜' => 0.375,
-        '⅝' => 0.625,
-        '⅞' => 0.875,
-        '⅟' => 1.0,
-        '↉' => 0.0,
-    };
+        // Stable, so items keep their first-seen order within a category
+        items.sort_by_key(|item| item.category);
 
-    pub fn numeric(c: &char) -> Option<f64> {
-        NUMERIC.get(c).copied()
+        ShoppingList { items, warnings }
     }
 
-    #[cfg(target_os = "windows")]
-    pub fn open_file(file_path: String) -> std::io::Result<()> {
-        Command::new("cmd")
-            .arg("/C")
-            .arg("start")
-            .arg("")
-            .arg(file_path)
-            .spawn()?;
-        Ok(())
+    pub fn make_shopping_list(components: Vec<Component>) -> String {
+        build_shopping_list(components, &HashSet::new(), UnitSystem::AsIs, None).to_string()
     }
 
-    #[cfg(target_os = "linux")]
-    pub fn open_file(file_path: String) -> std::io::Result<()> {
-        Command::new("xdg-open").arg(file_path).spawn()?;
-        Ok(())
+    pub fn shopping_list_to_json(list: &ShoppingList) -> Result<String, serde_json::Error> {
+        serde_json::to_string_pretty(list)
     }
 
-    #[cfg(target_os = "macos")]
-    pub fn open_file(file_path: String) -> std::io::Result<()> {
-        Command::new("open").arg(file_path).spawn()?;
-        Ok(())
+    fn csv_field(field: &str) -> String {
+        if field.contains([',', '"', '\n', '\r']) {
+            format!("\"{}\"", field.replace('"', "\"\""))
+        } else {
+            field.to_owned()
+        }
     }
 
-    pub async fn remove_duplicate_recipes(
-        recipes: Vec<api::Recipe>,
-        pool: &SqlitePool,
-    ) -> Result<Vec<api::Recipe>, sqlx::Error> {
-        let mut unique_recipes: Vec<api::Recipe> = Vec::new();
+    pub fn shopping_list_to_csv(list: &ShoppingList) -> String {
+        let mut rows: Vec<String> = vec!["ingredient,quantity,unit".to_owned()];
 
-        for recipe in recipes {
-            if !recipe_exists(recipe.id, pool).await? {
-                unique_recipes.push(recipe);
+        for item in &list.items {
+            let ingredient = csv_field(&item.ingredient);
+
+            if item.measurements.is_empty() {
+                rows.push(format!("{},,", ingredient));
+            }
+
+            for measurement in &item.measurements {
+                rows.push(format!(
+                    "{},{},{}",
+                    ingredient,
+                    measurement.quantity,
+                    csv_field(&measurement.unit)
+                ));
             }
         }
 
-        Ok(unique_recipes)
+        rows.join("\n") + "\n"
     }
 
-    pub fn validation_input<T>(prompt: Option<&str>, message_on_failure: Option<&str>) -> T
-    where
-        T: std::str::FromStr,
-        T: std::fmt::Display,
-        T::Err: std::fmt::Debug,
-    {
-        let prompt = prompt.unwrap_or("");
-        let ret: T;
+    // Just the items, without categories, so every line becomes a task when pasted
+    pub fn shopping_list_to_tasks(list: &ShoppingList, style: QuantityStyle) -> String {
+        list.items
+            .iter()
+            .map(|item| item.format_with(style) + "\n")
+            .collect()
+    }
 
-        loop {
-            print!("{}", prompt);
-            let n: Result<T, _> = try_read!();
+    pub fn shopping_list_to_markdown(list: &ShoppingList, style: QuantityStyle) -> String {
+        let mut lines: Vec<String> = Vec::new();
+        let mut current_category = None;
 
-            match n {
-                Ok(r) => {
-                    ret = r;
-                    break;
-                }
-                Err(_) => {
-                    match message_on_failure {
-                        Some(ref s) => eprintln!("{}", s),
-                        None => eprintln!("Your input could not be converted."),
-                    };
+        for item in &list.items {
+            if current_category != Some(item.category) {
+                if current_category.is_some() {
+                    lines.push(String::new());
                 }
+
+                lines.push(format!("### {}", item.category));
+                lines.push(String::new());
+                current_category = Some(item.category);
             }
+
+            lines.push(format!("- [ ] {}", item.format_with(style)));
         }
 
-        return ret;
+        lines.join("\n")
     }
 
-    pub async fn get_matching_recipes(
-        recipes: Vec<api::Recipe>,
-        n_recipes: i64,
-        pool: &SqlitePool,
-    ) -> Result<Vec<api::Recipe>, sqlx::Error> {
-        let mut scores: Vec<(api::Recipe, i64)> = Vec::new();
+    // Ingredients grouped under their section names, unlike the combined shopping list
+    pub fn recipe_ingredient_breakdown(recipe: &Recipe) -> String {
+        let mut lines: Vec<String> = Vec::new();
 
-        for recipe in recipes {
-            let mut recipe_score: i64 = 0;
+        for section in &recipe.sections {
+            let indent = match &section.name {
+                Some(name) => {
+                    lines.push(format!("{}:", name));
+                    "  "
+                }
+                None => "",
+            };
 
-            for tag in get_recipe_tags(recipe.id, pool).await? {
-                recipe_score += tag.likes;
+            for component in &section.components {
+                lines.push(format!("{}- {}", indent, component));
             }
-
-            scores.push((recipe, recipe_score));
         }
 
-        scores.sort_by(|a, b| a.1.cmp(&b.1));
+        lines.join("\n")
+    }
+
+    fn html_escape(s: &str) -> String {
+        s.replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;")
+            .replace('"', "&quot;")
+            .replace('\'', "&#39;")
+    }
+
+    // `notes` are shown under the recipe at the same position, like substitutions for avoided
+    // ingredients
+    pub fn recipes_to_html(
+        recipes: &[Recipe],
+        include_yields: bool,
+        include_instructions: bool,
+        notes: &[Vec<String>],
+    ) -> String {
+        recipes
+            .iter()
+            .enumerate()
+            .map(|(i, recipe)| {
+                let thumbnail = match &recipe.thumbnail_url {
+                    Some(url) => format!(
+                        "<img src=\"{}\" alt=\"{}\" width=\"200\">",
+                        html_escape(url),
+                        html_escape(&recipe.name)
+                    ),
+                    None => String::new(),
+                };
+
+                let mut html = format!(
+                    "<article>{}<h3><a href=\"{}\">{}</a>{}</h3>",
+                    thumbnail,
+                    html_escape(&recipe.url()),
+                    html_escape(&recipe.name),
+                    html_escape(&yields_suffix(recipe, include_yields))
+                );
+
+                if include_instructions && !recipe.instructions.is_empty() {
+                    html.push_str(&html_list("ol", recipe_steps(recipe)));
+                }
+
+                if let Some(notes) = notes.get(i).filter(|notes| !notes.is_empty()) {
+                    html.push_str(&html_list("ul", notes.iter().map(String::as_str)));
+                }
+
+                html + "</article>"
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    fn html_list<'a>(tag: &str, items: impl Iterator<Item = &'a str>) -> String {
+        let items: String = items
+            .map(|item| format!("<li>{}</li>", html_escape(item)))
+            .collect();
+
+        format!("<{}>{}</{}>", tag, items, tag)
+    }
+
+    // A standalone page with the shopping list as checkboxes followed by the recipes, which get
+    // the same extras as `recipes_to_html` gives them
+    pub fn render_html(
+        list: &ShoppingList,
+        recipes: &[Recipe],
+        include_yields: bool,
+        include_instructions: bool,
+        notes: &[Vec<String>],
+    ) -> String {
+        let mut lines: Vec<String> = vec![
+            "<!DOCTYPE html>".to_owned(),
+            "<html>".to_owned(),
+            "<head><meta charset=\"utf-8\"><title>Meal plan</title></head>".to_owned(),
+            "<body>".to_owned(),
+            "<h1>Shopping list</h1>".to_owned(),
+        ];
+        let mut current_category = None;
+
+        for item in &list.items {
+            if current_category != Some(item.category) {
+                if current_category.is_some() {
+                    lines.push("</ul>".to_owned());
+                }
+
+                lines.push(format!("<h2>{}</h2>", item.category));
+                lines.push("<ul>".to_owned());
+                current_category = Some(item.category);
+            }
+
+            lines.push(format!(
+                "<li><label><input type=\"checkbox\"> {}</label></li>",
+                html_escape(&item.to_string())
+            ));
+        }
+
+        if current_category.is_some() {
+            lines.push("</ul>".to_owned());
+        }
+
+        lines.push("<h1>Recipes</h1>".to_owned());
+        lines.push(recipes_to_html(
+            recipes,
+            include_yields,
+            include_instructions,
+            notes,
+        ));
+        lines.push("</body>".to_owned());
+        lines.push("</html>".to_owned());
+
+        lines.join("\n")
+    }
+
+    // `include_yields` adds the recipe's yield, like "Servings: 4", when it has one
+    pub fn recipe_line(recipe: &Recipe, include_yields: bool) -> String {
+        format!(
+            "{} — {}{}",
+            recipe.name,
+            recipe.url(),
+            yields_suffix(recipe, include_yields)
+        )
+    }
+
+    pub fn recipes_to_markdown(
+        recipes: &[Recipe],
+        include_yields: bool,
+        include_instructions: bool,
+    ) -> String {
+        recipes
+            .iter()
+            .map(|recipe| {
+                let line = format!(
+                    "- [{}]({}){}",
+                    recipe.name,
+                    recipe.url(),
+                    yields_suffix(recipe, include_yields)
+                );
+
+                if include_instructions && !recipe.instructions.is_empty() {
+                    // Indented so the steps nest under the recipe's bullet
+                    let steps = recipe_instructions(recipe)
+                        .lines()
+                        .map(|step| format!("    {}", step))
+                        .collect::<Vec<_>>()
+                        .join("\n");
+
+                    format!("{}\n{}", line, steps)
+                } else {
+                    line
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    // The recipe's steps as a numbered list, for cooking without opening the link
+    pub fn recipe_instructions(recipe: &Recipe) -> String {
+        recipe_steps(recipe)
+            .enumerate()
+            .map(|(i, text)| format!("{}. {}", i + 1, text))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    fn recipe_steps(recipe: &Recipe) -> impl Iterator<Item = &str> {
+        recipe
+            .instructions
+            .iter()
+            .map(|instruction| instruction.display_text.trim())
+            .filter(|text| !text.is_empty())
+    }
+
+    fn yields_suffix(recipe: &Recipe, include_yields: bool) -> String {
+        match recipe.yields.as_deref().map(str::trim) {
+            Some(yields) if include_yields && !yields.is_empty() => format!(" ({})", yields),
+            _ => String::new(),
+        }
+    }
+
+    pub mod models {
+        use std::{fmt::Display, ops::Add, time::Duration};
+
+        use chrono::{DateTime, NaiveDate};
+        use thiserror::Error;
+
+        use crate::utils::categories::Category;
+        use crate::utils::conversions::{self, convert, is_known_unit, BaseUnit};
+        use crate::utils::models::QuantityStyle;
+        use crate::utils::{numeric, numeric_ratio, parse_fraction, to_fraction_glyph};
+        use num_rational::Ratio;
+        use num_traits::{CheckedAdd, CheckedSub};
+        use reqwest::StatusCode;
+        use serde::{Deserialize, Serialize};
+
+        #[derive(Deserialize, Debug, Clone)]
+        pub struct Unit {
+            pub name: String,
+            pub abbreviation: String,
+        }
+
+        // A measurement as the API sends it, with the quantity still a string
+        #[derive(Deserialize)]
+        struct RawMeasurement {
+            id: i64,
+            quantity: String,
+            unit: Unit,
+        }
+
+        impl TryFrom<RawMeasurement> for Measurement {
+            type Error = String;
+
+            fn try_from(raw: RawMeasurement) -> Result<Self, Self::Error> {
+                // `f64::from_str` accepts "NaN" and "inf", which are never real amounts
+                let quantity = parse_quantity(&raw.quantity)
+                    .filter(|quantity| quantity.is_finite())
+                    .ok_or_else(|| format!("Unrecognized quantity: {:?}", raw.quantity))?;
+                let exact = parse_exact_quantity(&raw.quantity);
+
+                Ok(Measurement {
+                    id: raw.id,
+                    quantity: exact.as_ref().map_or(quantity, ratio_to_f64),
+                    exact,
+                    unit: raw.unit,
+                })
+            }
+        }
+
+        fn parse_quantity(numeric_str: &str) -> Option<f64> {
+            let parts: Vec<&str> = numeric_str.split_whitespace().collect();
+
+            match parts.as_slice() {
+                [single] => parse_single_quantity(single),
+                // Mixed fraction
+                [number_part, fraction_part] if !is_signed(fraction_part) => {
+                    let whole = number_part.parse::<f64>().ok()?;
+                    let fraction = parse_single_quantity(fraction_part)?;
+
+                    // "-1 1/2" is minus one and a half, not minus a half
+                    Some(if is_signed(number_part) {
+                        whole - fraction
+                    } else {
+                        whole + fraction
+                    })
+                }
+                _ => None,
+            }
+        }
+
+        // Whole numbers and fractions, which add up exactly where their `f64` values wouldn't
+        fn parse_exact_quantity(numeric_str: &str) -> Option<Ratio<i64>> {
+            let parts: Vec<&str> = numeric_str.split_whitespace().collect();
+
+            match parts.as_slice() {
+                [single] => parse_single_exact_quantity(single),
+                [number_part, fraction_part] if !is_signed(fraction_part) => {
+                    let whole = Ratio::from_integer(number_part.parse().ok()?);
+                    let fraction = parse_single_exact_quantity(fraction_part)?;
+
+                    // Too big to be exact, so the `f64` value is used instead
+                    if is_signed(number_part) {
+                        whole.checked_sub(&fraction)
+                    } else {
+                        whole.checked_add(&fraction)
+                    }
+                }
+                _ => None,
+            }
+        }
+
+        // Only the whole number of a mixed fraction may have a sign
+        fn is_signed(s: &str) -> bool {
+            s.starts_with(['-', '+'])
+        }
+
+        fn parse_single_exact_quantity(s: &str) -> Option<Ratio<i64>> {
+            if let Ok(whole) = s.parse::<i64>() {
+                Some(Ratio::from_integer(whole))
+            } else if let Some((numerator, denominator)) = s.split_once('/') {
+                let numerator: i64 = numerator.trim().parse().ok()?;
+                let denominator: i64 = denominator.trim().parse().ok()?;
+
+                (denominator != 0).then(|| Ratio::new(numerator, denominator))
+            } else {
+                let mut chars = s.chars();
+                let c = chars.next()?;
+
+                match chars.next() {
+                    Some(_) => None,
+                    None => numeric_ratio(&c),
+                }
+            }
+        }
+
+        fn ratio_to_f64(ratio: &Ratio<i64>) -> f64 {
+            *ratio.numer() as f64 / *ratio.denom() as f64
+        }
+
+        fn parse_single_quantity(s: &str) -> Option<f64> {
+            if let (true, Ok(parsed)) = (s.is_ascii(), s.parse::<f64>()) {
+                // Normal number
+                Some(parsed)
+            } else if s.contains('/') {
+                // ASCII fraction
+                parse_fraction(s)
+            } else {
+                // Unicode fraction
+                let mut chars = s.chars();
+                let c = chars.next()?;
+
+                match chars.next() {
+                    Some(_) => None,
+                    None => numeric(&c),
+                }
+            }
+        }
+
+        #[derive(Deserialize, Debug, Clone)]
+        #[serde(try_from = "RawMeasurement")]
+        pub struct Measurement {
+            id: i64,
+            pub quantity: f64,
+            // `quantity` as a fraction when the API wrote it as one
+            exact: Option<Ratio<i64>>,
+            pub unit: Unit,
+        }
+
+        #[derive(Deserialize, Debug, Clone)]
+        pub struct Ingredient {
+            pub id: i64,
+            pub display_singular: String,
+        }
+
+        #[derive(Deserialize, Debug, Clone)]
+        pub struct Component {
+            pub ingredient: Ingredient,
+            pub measurements: Vec<Measurement>,
+            // Amounts added to this one that none of its units could add up, like grams added
+            // to cups of something with no known density. Each is one amount in one or more units.
+            #[serde(skip)]
+            pub separate: Vec<Vec<Measurement>>,
+        }
+
+        impl Display for Component {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                // Later measurements are the same amount in other units
+                match self.measurements.iter().find(|m| m.quantity != 0.0) {
+                    Some(m) => write!(
+                        f,
+                        "{}: {} {}",
+                        self.ingredient.display_singular,
+                        format_quantity(m.quantity),
+                        m.unit.abbreviation
+                    ),
+                    None => write!(f, "{}", self.ingredient.display_singular),
+                }
+            }
+        }
+
+        #[derive(Error, Debug)]
+        pub enum ApiError {
+            #[error("the API key was rejected")]
+            Unauthorized,
+            #[error("too many requests to the API")]
+            RateLimited { retry_after: Option<Duration> },
+            #[error("the API responded with {0}")]
+            Http(StatusCode),
+            #[error("network error")]
+            Network(#[from] reqwest::Error),
+            #[error("failed to parse the API response")]
+            Parse(#[from] serde_json::Error),
+            #[error("request timed out after {0:?}")]
+            Timeout(Duration),
+        }
+
+        #[derive(Clone, Debug, Eq, Error, PartialEq)]
+        #[error("Components must have the same ingredients in order to add their amounts.")]
+        pub struct IncompatibleComponentError;
+
+        impl Measurement {
+            pub fn is_valid(&self) -> bool {
+                self.quantity.is_finite()
+            }
+
+            // Quantity in milliliters or grams, or `None` for units like "clove" or "pinch"
+            pub fn to_base(&self) -> Option<(f64, BaseUnit)> {
+                conversions::to_base(self.quantity, &self.unit)
+            }
+
+            // The fraction is dropped once `quantity` has been changed, like by scaling
+            fn exact(&self) -> Option<Ratio<i64>> {
+                self.exact
+                    .filter(|exact| ratio_to_f64(exact) == self.quantity)
+            }
+
+            // Amounts in units that aren't recognized, like "to taste", are never combined
+            fn combine(&self, other: &Measurement) -> Option<Measurement> {
+                if self.unit.name == other.unit.name {
+                    if !is_known_unit(&self.unit.name) {
+                        return None;
+                    }
+
+                    // Sums too big for a `Ratio` fall back to adding the `f64` values
+                    let exact = self
+                        .exact()
+                        .zip(other.exact())
+                        .and_then(|(a, b)| a.checked_add(&b));
+
+                    return Some(Measurement {
+                        id: self.id,
+                        quantity: exact
+                            .as_ref()
+                            .map_or(self.quantity + other.quantity, ratio_to_f64),
+                        exact,
+                        unit: self.unit.clone(),
+                    });
+                }
+
+                // Report the sum in whichever of the two units is larger
+                let (larger, smaller) = if convert(1.0, &self.unit, &other.unit)? >= 1.0 {
+                    (self, other)
+                } else {
+                    (other, self)
+                };
+
+                Some(Measurement {
+                    id: larger.id,
+                    quantity: larger.quantity
+                        + convert(smaller.quantity, &smaller.unit, &larger.unit)?,
+                    exact: None,
+                    unit: larger.unit.clone(),
+                })
+            }
+        }
+
+        impl Component {
+            // A component's measurements are the same amount in different units, so a repeated
+            // unit is a duplicate row rather than more of the ingredient and only the first is kept
+            pub fn normalize(&mut self) {
+                let mut seen: Vec<String> = Vec::new();
+
+                self.measurements.retain(|m| {
+                    if seen.contains(&m.unit.name) {
+                        false
+                    } else {
+                        seen.push(m.unit.name.clone());
+                        true
+                    }
+                });
+            }
+
+            // The main amount followed by the separate ones, leaving out empty ones
+            fn into_amounts(self) -> impl Iterator<Item = Vec<Measurement>> {
+                std::iter::once(self.measurements)
+                    .chain(self.separate)
+                    .filter(|amount| !amount.is_empty())
+            }
+        }
+
+        // Both amounts summed in every unit they can both be written in, or `None` when none of
+        // their units add up. Units only one of them has are dropped, since they would no longer
+        // describe the whole amount.
+        fn add_amounts(lhs: &[Measurement], rhs: &[Measurement]) -> Option<Vec<Measurement>> {
+            let sum: Vec<Measurement> = pair_measurements(lhs, rhs).into_iter().flatten().collect();
+
+            (!sum.is_empty()).then_some(sum)
+        }
+
+        // The sum for each of `lhs`'s measurements that found one in `rhs` to add to.
+        // Measurements in the same unit are paired first so that a component listing both cups
+        // and milliliters doesn't get its cups merged into the milliliters.
+        fn pair_measurements(lhs: &[Measurement], rhs: &[Measurement]) -> Vec<Option<Measurement>> {
+            let mut combined: Vec<Option<Measurement>> = vec![None; lhs.len()];
+            let mut rhs_used = vec![false; rhs.len()];
+
+            for same_unit_only in [true, false] {
+                for (i, measurement) in lhs.iter().enumerate() {
+                    if combined[i].is_some() {
+                        continue;
+                    }
+
+                    for (j, rhs_measurement) in rhs.iter().enumerate() {
+                        if rhs_used[j]
+                            || (same_unit_only
+                                && measurement.unit.name != rhs_measurement.unit.name)
+                        {
+                            continue;
+                        }
+
+                        if let Some(sum) = measurement.combine(rhs_measurement) {
+                            combined[i] = Some(sum);
+                            rhs_used[j] = true;
+                            break;
+                        }
+                    }
+                }
+            }
+
+            combined
+        }
+
+        impl Add for Component {
+            type Output = Result<Self, IncompatibleComponentError>;
+
+            // Each of `rhs`'s amounts is added to the first of `self`'s that it shares a unit
+            // with, and kept as a separate amount when it shares none
+            fn add(self, rhs: Self) -> Self::Output {
+                if self.ingredient.id != rhs.ingredient.id {
+                    return Err(IncompatibleComponentError);
+                }
+
+                let ingredient = self.ingredient.clone();
+                let mut amounts: Vec<Vec<Measurement>> = self.into_amounts().collect();
+
+                for amount in rhs.into_amounts() {
+                    let sum = amounts
+                        .iter()
+                        .enumerate()
+                        .find_map(|(i, total)| add_amounts(total, &amount).map(|sum| (i, sum)));
+
+                    match sum {
+                        Some((i, sum)) => amounts[i] = sum,
+                        None => amounts.push(amount),
+                    }
+                }
+
+                let mut amounts = amounts.into_iter();
+
+                Ok(Component {
+                    ingredient,
+                    measurements: amounts.next().unwrap_or_default(),
+                    separate: amounts.collect(),
+                })
+            }
+        }
+
+        #[derive(Deserialize, Debug, Clone)]
+        pub struct Section {
+            // Only set on recipes with several parts, like "For the sauce"
+            pub name: Option<String>,
+            pub components: Vec<Component>,
+        }
+
+        #[derive(Deserialize, Debug, Clone)]
+        pub struct Instruction {
+            pub display_text: String,
+        }
+
+        #[derive(Deserialize, Debug, Clone)]
+        pub struct Tag {
+            pub id: i64,
+            pub name: String,
+            pub display_name: String,
+        }
+
+        #[derive(Deserialize, Debug, Clone)]
+        pub struct Recipe {
+            pub name: String,
+            pub id: i64,
+            pub slug: String,
+            pub num_servings: Option<i64>,
+            pub total_time_minutes: Option<i64>,
+            pub thumbnail_url: Option<String>,
+            pub sections: Vec<Section>,
+            pub tags: Vec<Tag>,
+            #[serde(default)]
+            pub nutrition: Option<Nutrition>,
+            // Unix timestamp in seconds
+            #[serde(default)]
+            pub created_at: Option<i64>,
+            // Like "Servings: 4"
+            #[serde(default)]
+            pub yields: Option<String>,
+            #[serde(default)]
+            pub instructions: Vec<Instruction>,
+        }
+
+        // Per serving. Recipes without nutrition data come back as an empty object.
+        #[derive(Deserialize, Debug, Clone, Default)]
+        pub struct Nutrition {
+            pub calories: Option<i64>,
+        }
+
+        impl Recipe {
+            pub fn url(&self) -> String {
+                format!("https://tasty.co/recipe/{}", self.slug)
+            }
+
+            pub fn created_date(&self) -> Option<NaiveDate> {
+                self.created_at
+                    .and_then(|seconds| DateTime::from_timestamp(seconds, 0))
+                    .map(|created| created.date_naive())
+            }
+        }
+
+        #[derive(Deserialize, Debug)]
+        pub struct RecipeList {
+            pub count: i32,
+            pub results: Vec<Recipe>,
+        }
+
+        #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+        pub struct ShoppingMeasurement {
+            pub quantity: f64,
+            pub unit: String,
+            // The unit's full name, like "tablespoon", which conversions look units up by
+            #[serde(skip)]
+            pub unit_name: String,
+            // The same amount in other units, like grams for a cup of flour
+            #[serde(default, skip_serializing_if = "Vec::is_empty")]
+            pub alternates: Vec<ShoppingMeasurement>,
+        }
+
+        #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+        pub struct ShoppingItem {
+            pub ingredient_id: i64,
+            pub ingredient: String,
+            pub category: Category,
+            pub measurements: Vec<ShoppingMeasurement>,
+        }
+
+        #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+        pub struct ShoppingList {
+            pub items: Vec<ShoppingItem>,
+            // Problems noticed while combining amounts, for the user to double check
+            #[serde(default, skip_serializing_if = "Vec::is_empty")]
+            pub warnings: Vec<String>,
+        }
+
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        pub struct Summary {
+            pub recipe_count: usize,
+            pub ingredient_count: usize,
+        }
+
+        impl ShoppingList {
+            pub fn summary(&self, recipe_count: usize) -> Summary {
+                Summary {
+                    recipe_count,
+                    ingredient_count: self.items.len(),
+                }
+            }
+        }
+
+        impl Display for Summary {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                write!(
+                    f,
+                    "{} recipes, {} ingredients",
+                    self.recipe_count, self.ingredient_count
+                )
+            }
+        }
+
+        fn format_quantity(quantity: f64) -> String {
+            // Rounding error can leave amounts just below zero, which would print as "-0.00"
+            let quantity = if quantity < 0.0 && quantity > -0.005 {
+                0.0
+            } else {
+                quantity
+            };
+
+            if quantity.fract() == 0.0 {
+                format!("{}", quantity as i64)
+            } else {
+                format!("{:.2}", quantity)
+            }
+        }
+
+        // Renders "1½" instead of "1.50" where the fractional part has a glyph
+        fn format_quantity_as_fraction(quantity: f64) -> String {
+            let whole = quantity.trunc();
+
+            match to_fraction_glyph(quantity - whole) {
+                Some(glyph) if whole == 0.0 => glyph.to_string(),
+                Some(glyph) => format!("{}{}", whole as i64, glyph),
+                None => format_quantity(quantity),
+            }
+        }
+
+        impl ShoppingItem {
+            pub fn format_with(&self, style: QuantityStyle) -> String {
+                if self.measurements.is_empty() {
+                    return self.ingredient.clone();
+                }
+
+                let format_amount = |m: &ShoppingMeasurement| {
+                    let quantity = match style {
+                        QuantityStyle::Decimal => format_quantity(m.quantity),
+                        QuantityStyle::Fraction => format_quantity_as_fraction(m.quantity),
+                    };
+
+                    format!("{} {}", quantity, m.unit)
+                };
+
+                // The same amount in other units reads "1 cup / 125 g"
+                let mut amounts = self.measurements.iter().map(|m| {
+                    std::iter::once(m)
+                        .chain(&m.alternates)
+                        .map(format_amount)
+                        .collect::<Vec<_>>()
+                        .join(" / ")
+                });
+
+                let first = amounts.next().unwrap_or_default();
+                let rest = amounts.collect::<Vec<_>>();
+
+                // Amounts that couldn't be added to the first one, like "2 cup (+ 100 g)"
+                if rest.is_empty() {
+                    format!("{}: {}", self.ingredient, first)
+                } else {
+                    format!("{}: {} (+ {})", self.ingredient, first, rest.join(" + "))
+                }
+            }
+        }
+
+        impl Display for ShoppingItem {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                write!(f, "{}", self.format_with(QuantityStyle::Decimal))
+            }
+        }
+
+        impl ShoppingList {
+            pub fn format_with(&self, style: QuantityStyle) -> String {
+                let mut lines: Vec<String> = Vec::new();
+                let mut current_category: Option<Category> = None;
+
+                for item in &self.items {
+                    if current_category != Some(item.category) {
+                        if current_category.is_some() {
+                            lines.push(String::new());
+                        }
+
+                        lines.push(format!("[{}]", item.category));
+                        current_category = Some(item.category);
+                    }
+
+                    lines.push(item.format_with(style));
+                }
+
+                lines.join("\n")
+            }
+        }
+
+        impl Display for ShoppingList {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                write!(f, "{}", self.format_with(QuantityStyle::Decimal))
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use models::Summary;
+        use serde_json::json;
+
+        // `amounts` are (quantity, unit name, abbreviation), the way the API writes them
+        fn component(id: i64, name: &str, amounts: &[(&str, &str, &str)]) -> Component {
+            let measurements: Vec<serde_json::Value> = amounts
+                .iter()
+                .enumerate()
+                .map(|(i, (quantity, unit, abbreviation))| {
+                    json!({
+                        "id": i,
+                        "quantity": quantity,
+                        "unit": { "name": unit, "abbreviation": abbreviation },
+                    })
+                })
+                .collect();
+
+            serde_json::from_value(json!({
+                "ingredient": { "id": id, "display_singular": name },
+                "measurements": measurements,
+            }))
+            .unwrap()
+        }
+
+        fn list_lines(components: Vec<Component>, system: UnitSystem) -> Vec<String> {
+            build_shopping_list(components, &HashSet::new(), system, None)
+                .items
+                .iter()
+                .map(ToString::to_string)
+                .collect()
+        }
+
+        #[test]
+        fn same_amount_in_two_units_is_shown_as_equivalent() {
+            let flour = component(1, "flour", &[("1", "cup", "cup"), ("125", "gram", "g")]);
+
+            assert_eq!(
+                list_lines(vec![flour], UnitSystem::AsIs),
+                ["flour: 1 cup / 125 g"]
+            );
+        }
+
+        #[test]
+        fn equivalent_units_are_summed_side_by_side() {
+            let flour = component(1, "flour", &[("1", "cup", "cup"), ("125", "gram", "g")]);
+
+            assert_eq!(
+                list_lines(vec![flour.clone(), flour], UnitSystem::AsIs),
+                ["flour: 2 cup / 250 g"]
+            );
+        }
+
+        #[test]
+        fn converted_units_of_one_amount_collapse_to_one() {
+            let milk = component(
+                1,
+                "milk",
+                &[("1 ⅛", "cup", "cup"), ("270", "milliliter", "ml")],
+            );
+
+            assert_eq!(
+                list_lines(vec![milk.clone()], UnitSystem::Metric),
+                ["milk: 266.16 ml"]
+            );
+            assert_eq!(
+                list_lines(vec![milk], UnitSystem::Imperial),
+                ["milk: 1.12 cup"]
+            );
+        }
+
+        #[test]
+        fn converted_units_keep_other_dimensions_as_equivalents() {
+            let flour = component(1, "flour", &[("1", "cup", "cup"), ("125", "gram", "g")]);
+
+            assert_eq!(
+                list_lines(vec![flour], UnitSystem::Metric),
+                ["flour: 236.59 ml / 125 g"]
+            );
+        }
+
+        #[test]
+        fn amounts_that_cannot_be_added_are_listed_separately() {
+            let cups = component(1, "flour", &[("2", "cup", "cup")]);
+            let grams = component(1, "flour", &[("100", "gram", "g")]);
+
+            assert_eq!(
+                list_lines(vec![cups, grams], UnitSystem::AsIs),
+                ["flour: 2 cup (+ 100 g)"]
+            );
+        }
+
+        // The first item's amounts as (quantity, unit)
+        fn first_item_amounts(components: Vec<Component>) -> Vec<(f64, String)> {
+            build_shopping_list(components, &HashSet::new(), UnitSystem::AsIs, None).items[0]
+                .measurements
+                .iter()
+                .map(|m| (m.quantity, m.unit.clone()))
+                .collect()
+        }
+
+        #[test]
+        fn cups_and_tablespoons_add_up() {
+            let amounts = first_item_amounts(vec![
+                component(1, "flour", &[("1", "cup", "cup")]),
+                component(1, "flour", &[("2", "tablespoon", "tbsp")]),
+            ]);
+
+            assert_eq!(amounts.len(), 1);
+            assert_eq!(amounts[0].1, "cup");
+            assert!((amounts[0].0 - 1.125).abs() < 1e-6);
+        }
+
+        // Sums are written in the larger of the two units
+        #[test]
+        fn grams_and_kilograms_add_up() {
+            let amounts = first_item_amounts(vec![
+                component(1, "flour", &[("500", "gram", "g")]),
+                component(1, "flour", &[("1", "kilogram", "kg")]),
+            ]);
+
+            assert_eq!(amounts.len(), 1);
+            assert_eq!(amounts[0].1, "kg");
+            assert!((amounts[0].0 - 1.5).abs() < 1e-6);
+        }
+
+        #[test]
+        fn milliliters_and_grams_are_kept_apart() {
+            let milk = vec![
+                component(1, "milk", &[("100", "milliliter", "ml")]),
+                component(1, "milk", &[("50", "gram", "g")]),
+            ];
+
+            assert_eq!(
+                list_lines(milk, UnitSystem::AsIs),
+                ["milk: 100 ml (+ 50 g)"]
+            );
+        }
+
+        #[test]
+        fn different_ingredients_do_not_add() {
+            let flour = component(1, "flour", &[("1", "cup", "cup")]);
+            let sugar = component(2, "sugar", &[("1", "cup", "cup")]);
+
+            assert!((flour.clone() + sugar.clone()).is_err());
+            assert_eq!(
+                list_lines(vec![flour, sugar], UnitSystem::AsIs),
+                ["flour: 1 cup", "sugar: 1 cup"]
+            );
+        }
+
+        #[test]
+        fn normalize_keeps_the_first_of_a_repeated_unit() {
+            let mut flour = component(
+                1,
+                "flour",
+                &[
+                    ("1", "cup", "cup"),
+                    ("1", "cup", "cup"),
+                    ("125", "gram", "g"),
+                ],
+            );
+            flour.normalize();
+
+            assert_eq!(
+                list_lines(vec![flour], UnitSystem::AsIs),
+                ["flour: 1 cup / 125 g"]
+            );
+        }
+
+        #[test]
+        fn html_recipes_show_yields_steps_and_notes() {
+            let recipe: Recipe = serde_json::from_value(json!({
+                "name": "Mac & cheese",
+                "id": 1,
+                "slug": "mac-and-cheese",
+                "sections": [],
+                "tags": [],
+                "yields": "Servings: 4",
+                "instructions": [
+                    { "display_text": "Boil the pasta." },
+                    { "display_text": " " },
+                    { "display_text": "Stir in the cheese." },
+                ],
+            }))
+            .unwrap();
+            let notes = vec![vec!["Swap milk for oat milk".to_owned()]];
+
+            let html = recipes_to_html(std::slice::from_ref(&recipe), true, true, &notes);
+
+            assert!(html.contains("Mac &amp; cheese</a> (Servings: 4)</h3>"));
+            assert!(html.contains("<ol><li>Boil the pasta.</li><li>Stir in the cheese.</li></ol>"));
+            assert!(html.contains("<ul><li>Swap milk for oat milk</li></ul>"));
+            assert_eq!(
+                recipes_to_html(&[recipe], false, false, &[]),
+                "<article><h3><a href=\"https://tasty.co/recipe/mac-and-cheese\">Mac &amp; cheese</a></h3></article>"
+            );
+        }
+
+        fn warnings(components: Vec<Component>) -> Vec<String> {
+            build_shopping_list(components, &HashSet::new(), UnitSystem::AsIs, None).warnings
+        }
+
+        #[test]
+        fn one_amount_in_two_units_is_not_warned_about() {
+            let flour = component(1, "flour", &[("1", "cup", "cup"), ("125", "gram", "g")]);
+
+            assert!(warnings(vec![flour.clone(), flour]).is_empty());
+        }
+
+        #[test]
+        fn amounts_in_different_dimensions_are_warned_about() {
+            let cups = component(1, "flour", &[("2", "cup", "cup")]);
+            let grams = component(1, "flour", &[("100", "gram", "g")]);
+
+            assert_eq!(
+                warnings(vec![cups, grams]),
+                ["Amounts in units that can't be added together were listed separately for flour"]
+            );
+        }
+
+        #[test]
+        fn unknown_units_are_only_warned_about_when_left_apart() {
+            let handful = component(1, "spinach", &[("1", "handful", "handful")]);
+
+            assert!(warnings(vec![handful.clone()]).is_empty());
+            assert_eq!(
+                warnings(vec![handful.clone(), handful]),
+                ["Amounts in these units weren't recognized, so they couldn't be added up: handful"]
+            );
+        }
+
+        #[test]
+        fn units_missing_from_one_amount_are_dropped_when_merging() {
+            let both = component(1, "flour", &[("1", "cup", "cup"), ("125", "gram", "g")]);
+            let grams = component(1, "flour", &[("100", "gram", "g")]);
+
+            assert_eq!(
+                list_lines(vec![both, grams], UnitSystem::AsIs),
+                ["flour: 225 g"]
+            );
+        }
+
+        #[test]
+        fn json_lists_items_with_their_amounts() {
+            let flour = component(1, "flour", &[("1", "cup", "cup"), ("125", "gram", "g")]);
+            let list = build_shopping_list(vec![flour], &HashSet::new(), UnitSystem::AsIs, None);
+
+            let json: serde_json::Value =
+                serde_json::from_str(&shopping_list_to_json(&list).unwrap()).unwrap();
+
+            assert_eq!(
+                json,
+                json!({
+                    "items": [{
+                        "ingredient_id": 1,
+                        "ingredient": "flour",
+                        "category": "Baking",
+                        "measurements": [{
+                            "quantity": 1.0,
+                            "unit": "cup",
+                            "alternates": [{ "quantity": 125.0, "unit": "g" }],
+                        }],
+                    }],
+                })
+            );
+        }
+
+        // Answers each request with the next of `responses` and returns the URL to send them to
+        async fn serve(responses: Vec<&'static str>) -> String {
+            use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let url = format!("http://{}", listener.local_addr().unwrap());
+
+            tokio::spawn(async move {
+                for response in responses {
+                    let (mut stream, _) = listener.accept().await.unwrap();
+                    let mut request: Vec<u8> = Vec::new();
+                    let mut buffer = [0; 1024];
+
+                    while !request.ends_with(b"\r\n\r\n") {
+                        let read = stream.read(&mut buffer).await.unwrap();
+                        if read == 0 {
+                            break;
+                        }
+                        request.extend_from_slice(&buffer[..read]);
+                    }
+
+                    stream.write_all(response.as_bytes()).await.unwrap();
+                }
+            });
+
+            url
+        }
+
+        const OK: &str = "HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok";
+        const UNAVAILABLE: &str =
+            "HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+        const RATE_LIMITED: &str = "HTTP/1.1 429 Too Many Requests\r\nRetry-After: 1\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+
+        #[tokio::test]
+        async fn unavailable_responses_are_retried_after_a_backoff() {
+            let url = serve(vec![UNAVAILABLE, OK]).await;
+            let started = std::time::Instant::now();
+
+            let body = get_body_with_retries(&url, "/", &[], "key", 1)
+                .await
+                .unwrap();
+
+            assert_eq!(body, "ok");
+            assert!(started.elapsed() >= INITIAL_BACKOFF);
+        }
+
+        #[tokio::test]
+        async fn retries_wait_as_long_as_retry_after_says() {
+            let url = serve(vec![RATE_LIMITED, OK]).await;
+            let started = std::time::Instant::now();
+
+            let body = get_body_with_retries(&url, "/", &[], "key", 1)
+                .await
+                .unwrap();
+
+            assert_eq!(body, "ok");
+            assert!(started.elapsed() >= Duration::from_secs(1));
+        }
+
+        #[tokio::test]
+        async fn the_last_error_is_returned_once_retries_run_out() {
+            let url = serve(vec![RATE_LIMITED, RATE_LIMITED]).await;
+
+            let result = get_body_with_retries(&url, "/", &[], "key", 1).await;
+
+            assert!(matches!(
+                result,
+                Err(ApiError::RateLimited {
+                    retry_after: Some(wait)
+                }) if wait == Duration::from_secs(1)
+            ));
+        }
+
+        // The quantity a measurement written as `quantity` is read as, or `None` when it's rejected
+        fn quantity(quantity: &str) -> Option<f64> {
+            serde_json::from_value::<Measurement>(json!({
+                "id": 0,
+                "quantity": quantity,
+                "unit": { "name": "cup", "abbreviation": "cup" },
+            }))
+            .ok()
+            .map(|m| m.quantity)
+        }
+
+        #[test]
+        fn unexpected_quantities_are_rejected() {
+            for garbage in ["1-2", "", "abc", "NaN", "inf", "1 2 3", "1 -1/2"] {
+                assert_eq!(quantity(garbage), None, "{:?}", garbage);
+            }
+        }
+
+        #[test]
+        fn mixed_fractions_are_read() {
+            assert_eq!(quantity("1/2"), Some(0.5));
+            assert_eq!(quantity("1 1/2"), Some(1.5));
+            assert_eq!(quantity("1 ½"), Some(1.5));
+        }
+
+        #[test]
+        fn negative_mixed_fractions_keep_their_sign() {
+            assert_eq!(quantity("-1 1/2"), Some(-1.5));
+            assert_eq!(quantity("-1 ½"), Some(-1.5));
+            assert_eq!(quantity("-1.5 1/2"), Some(-2.0));
+        }
+
+        #[test]
+        fn slash_fractions_are_read() {
+            assert_eq!(quantity("3/4"), Some(0.75));
+            assert_eq!(quantity("1 1/2"), Some(1.5));
+            assert_eq!(quantity("2 1/3"), Some(7.0 / 3.0));
+            assert_eq!(quantity("1/0"), None);
+            assert_eq!(quantity("1 1/0"), None);
+        }
+
+        #[test]
+        fn thousands_of_components_add_up_in_first_seen_order() {
+            let components: Vec<Component> = (0..3000)
+                .map(|n| {
+                    let id = n % 100;
+                    let name = format!("ingredient {}", id);
+
+                    if (n / 100) % 2 == 0 {
+                        component(id, &name, &[("1", "cup", "cup")])
+                    } else {
+                        component(id, &name, &[("16", "tablespoon", "tbsp")])
+                    }
+                })
+                .collect();
+
+            let list = build_shopping_list(components, &HashSet::new(), UnitSystem::AsIs, None);
+
+            assert_eq!(list.items.len(), 100);
+            for (id, item) in list.items.iter().enumerate() {
+                assert_eq!(item.ingredient_id, id as i64);
+                assert_eq!(item.measurements.len(), 1);
+                assert_eq!(item.measurements[0].unit, "cup");
+                assert!((item.measurements[0].quantity - 30.0).abs() < 1e-3);
+            }
+        }
+
+        #[test]
+        fn markdown_lists_are_checkboxes_under_category_headings() {
+            let list = build_shopping_list(
+                vec![
+                    component(1, "flour", &[("1 ½", "cup", "cup")]),
+                    component(2, "milk", &[("1", "cup", "cup")]),
+                ],
+                &HashSet::new(),
+                UnitSystem::AsIs,
+                None,
+            );
+
+            assert_eq!(
+                shopping_list_to_markdown(&list, QuantityStyle::Fraction),
+                "### Dairy\n\n- [ ] milk: 1 cup\n\n### Baking\n\n- [ ] flour: 1½ cup"
+            );
+        }
+
+        #[test]
+        fn csv_fields_with_separators_or_quotes_are_quoted() {
+            assert_eq!(csv_field("flour"), "flour");
+            assert_eq!(csv_field("salt, kosher"), "\"salt, kosher\"");
+            assert_eq!(csv_field("6\" tortilla"), "\"6\"\" tortilla\"");
+            assert_eq!(csv_field("a\nb"), "\"a\nb\"");
+        }
+
+        #[test]
+        fn csv_rows_without_an_amount_leave_it_empty() {
+            let list = build_shopping_list(
+                vec![
+                    component(1, "salt, kosher", &[("0", "", "")]),
+                    component(2, "flour", &[("2", "cup", "cup")]),
+                ],
+                &HashSet::new(),
+                UnitSystem::AsIs,
+                None,
+            );
+
+            assert_eq!(
+                shopping_list_to_csv(&list),
+                "ingredient,quantity,unit\nflour,2,cup\n\"salt, kosher\",,\n"
+            );
+        }
+
+        #[test]
+        fn summaries_count_recipes_and_distinct_ingredients() {
+            let list = build_shopping_list(
+                vec![
+                    component(1, "flour", &[("1", "cup", "cup")]),
+                    component(2, "milk", &[("1", "cup", "cup")]),
+                    component(1, "flour", &[("1", "cup", "cup")]),
+                ],
+                &HashSet::new(),
+                UnitSystem::AsIs,
+                None,
+            );
+
+            let summary = list.summary(2);
+
+            assert_eq!(
+                summary,
+                Summary {
+                    recipe_count: 2,
+                    ingredient_count: 2
+                }
+            );
+            assert_eq!(summary.to_string(), "2 recipes, 2 ingredients");
+        }
+
+        #[test]
+        fn statuses_map_to_their_errors() {
+            let wait = Some(Duration::from_secs(3));
+
+            assert!(status_error(StatusCode::OK, None).is_none());
+            assert!(matches!(
+                status_error(StatusCode::UNAUTHORIZED, None),
+                Some(ApiError::Unauthorized)
+            ));
+            assert!(matches!(
+                status_error(StatusCode::FORBIDDEN, None),
+                Some(ApiError::Unauthorized)
+            ));
+            assert!(matches!(
+                status_error(StatusCode::TOO_MANY_REQUESTS, wait),
+                Some(ApiError::RateLimited { retry_after }) if retry_after == wait
+            ));
+            assert!(matches!(
+                status_error(StatusCode::INTERNAL_SERVER_ERROR, None),
+                Some(ApiError::Http(StatusCode::INTERNAL_SERVER_ERROR))
+            ));
+            assert!(matches!(
+                status_error(StatusCode::NOT_FOUND, None),
+                Some(ApiError::Http(StatusCode::NOT_FOUND))
+            ));
+        }
+
+        fn rounded(quantity: &str, unit: &str, tolerance: f64) -> f64 {
+            let measurement = &component(1, "x", &[(quantity, unit, unit)]).measurements[0];
+            round_measurement_within(measurement, tolerance).quantity
+        }
+
+        #[test]
+        fn amounts_round_to_the_nearest_practical_step() {
+            assert_eq!(rounded("1.1", "cup", DEFAULT_ROUNDING_TOLERANCE), 1.125);
+            assert_eq!(rounded("2.9", "clove", DEFAULT_ROUNDING_TOLERANCE), 3.0);
+            assert_eq!(
+                rounded("47", "milliliter", DEFAULT_ROUNDING_TOLERANCE),
+                45.0
+            );
+        }
+
+        #[test]
+        fn amounts_that_would_move_too_far_are_left_alone() {
+            // Rounding a pinch up to a quarter teaspoon would more than double it
+            assert_eq!(rounded("0.1", "teaspoon", DEFAULT_ROUNDING_TOLERANCE), 0.1);
+            assert_eq!(rounded("1.1", "cup", 0.0), 1.1);
+            assert_eq!(rounded("1.1", "cup", 0.01), 1.1);
+            assert_eq!(rounded("1.1", "cup", 0.05), 1.125);
+        }
+
+        #[test]
+        fn units_without_a_step_are_not_rounded() {
+            assert_eq!(rounded("1.3", "pinch", DEFAULT_ROUNDING_TOLERANCE), 1.3);
+        }
+
+        #[test]
+        fn thirds_add_up_exactly() {
+            let third = component(1, "milk", &[("1/3", "cup", "cup")]);
+
+            let amounts = first_item_amounts(vec![third.clone(), third.clone(), third]);
+
+            assert_eq!(amounts, [(1.0, "cup".to_owned())]);
+        }
+
+        #[test]
+        fn sixths_add_up_exactly() {
+            let amounts = first_item_amounts(vec![
+                component(1, "milk", &[("1 ⅙", "cup", "cup")]),
+                component(1, "milk", &[("⅚", "cup", "cup")]),
+                component(1, "milk", &[("1/6", "cup", "cup")]),
+                component(1, "milk", &[("5/6", "cup", "cup")]),
+            ]);
+
+            assert_eq!(amounts, [(3.0, "cup".to_owned())]);
+        }
+
+        #[test]
+        fn fractions_too_big_to_add_exactly_add_as_decimals() {
+            // The common denominator doesn't fit in an i64
+            let amounts = first_item_amounts(vec![
+                component(1, "salt", &[("1/9223372036854775807", "cup", "cup")]),
+                component(1, "salt", &[("1/9223372036854775806", "cup", "cup")]),
+            ]);
+            assert_eq!(amounts[0].0, 2.0 / 9223372036854775808.0);
+
+            assert_eq!(
+                quantity("9223372036854775807 1/2"),
+                Some(9223372036854775807.5)
+            );
+        }
+    }
+}
+
+pub mod utils {
+    use crate::api;
+    use crate::database::{
+        get_recently_used_recipe_ids, get_recipe_ids_with_status, get_tags_for_recipes,
+    };
+    use chrono::{
+        format::{Item, StrftimeItems},
+        DateTime, NaiveDate, TimeZone, Utc,
+    };
+    use directories::ProjectDirs;
+    use log::warn;
+    use models::{Choice, InputError, RecipeFilter, RecipeStatus, Strategy};
+    use num_rational::Ratio;
+    use phf::phf_map;
+    use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+    use sqlx::SqlitePool;
+    use std::{
+        collections::HashSet,
+        env,
+        fmt::Display,
+        io::{self, BufRead, Write},
+        path::{Path, PathBuf},
+        process::Command,
+    };
+
+    const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+    // Like "06:30 pm"
+    pub const DEFAULT_TIME_FORMAT: &str = "%I:%M %P";
+
+    // Each glyph's value as (numerator, denominator)
+    static NUMERIC: phf::Map<char, (i64, i64)> = phf_map! {
+        '¼' => (1, 4),
+        '½' => (1, 2),
+        '¾' => (3, 4),
+        '⅐' => (1, 7),
+        '⅑' => (1, 9),
+        '⅒' => (1, 10),
+        '⅓' => (1, 3),
+        '⅔' => (2, 3),
+        '⅕' => (1, 5),
+        '⅖' => (2, 5),
+        '⅗' => (3, 5),
+        '⅘' => (4, 5),
+        '⅙' => (1, 6),
+        '⅚' => (5, 6),
+        '⅛' => (1, 8),
+        '⅜' => (3, 8),
+        '⅝' => (5, 8),
+        '⅞' => (7, 8),
+        '⅟' => (1, 1),
+        '↉' => (0, 3),
+    };
+
+    pub fn numeric(c: &char) -> Option<f64> {
+        NUMERIC
+            .get(c)
+            .map(|(numerator, denominator)| *numerator as f64 / *denominator as f64)
+    }
+
+    pub fn numeric_ratio(c: &char) -> Option<Ratio<i64>> {
+        NUMERIC
+            .get(c)
+            .map(|(numerator, denominator)| Ratio::new(*numerator, *denominator))
+    }
+
+    // Glyphs for whole numbers are left out, since those print fine as digits
+    pub fn to_fraction_glyph(value: f64) -> Option<char> {
+        NUMERIC
+            .keys()
+            .filter_map(|glyph| Some((*glyph, numeric(glyph)?)))
+            .filter(|(_, glyph_value)| *glyph_value > 0.0 && *glyph_value < 1.0)
+            .find(|(_, glyph_value)| (value - *glyph_value).abs() < 0.001)
+            .map(|(glyph, _)| glyph)
+    }
+
+    pub fn parse_fraction(s: &str) -> Option<f64> {
+        let (numerator, denominator) = s.split_once('/')?;
+        let numerator: f64 = numerator.trim().parse().ok()?;
+        let denominator: f64 = denominator.trim().parse().ok()?;
+
+        if denominator == 0.0 {
+            return None;
+        }
+
+        Some(numerator / denominator)
+    }
+
+    #[cfg(all(target_os = "windows", not(test)))]
+    pub fn open_file(file_path: &Path) -> std::io::Result<()> {
+        Command::new("cmd")
+            .arg("/C")
+            .arg("start")
+            .arg("")
+            .arg(file_path)
+            .spawn()?;
+        Ok(())
+    }
+
+    #[cfg(all(target_os = "linux", not(test)))]
+    pub fn open_file(file_path: &Path) -> std::io::Result<()> {
+        Command::new("xdg-open").arg(file_path).spawn()?;
+        Ok(())
+    }
+
+    #[cfg(all(target_os = "macos", not(test)))]
+    pub fn open_file(file_path: &Path) -> std::io::Result<()> {
+        Command::new("open").arg(file_path).spawn()?;
+        Ok(())
+    }
+
+    #[cfg(all(
+        not(any(target_os = "windows", target_os = "linux", target_os = "macos")),
+        not(test)
+    ))]
+    pub fn open_file(_file_path: &Path) -> std::io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "opening files is not supported on this platform",
+        ))
+    }
+
+    // Tests never launch a viewer
+    #[cfg(test)]
+    pub fn open_file(_file_path: &Path) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    // Prefixes of the files a prepare run writes, each followed by the date
+    const OUTPUT_PREFIXES: [&str; 4] = [
+        "shopping-list-",
+        "shopping-tasks-",
+        "recipes-",
+        "meal-plan-",
+    ];
+
+    fn output_date(file_name: &str) -> Option<NaiveDate> {
+        let (stem, _) = file_name.rsplit_once('.')?;
+
+        OUTPUT_PREFIXES
+            .iter()
+            .find_map(|prefix| stem.strip_prefix(prefix))
+            .and_then(|date| date.parse().ok())
+    }
+
+    // The generated files from the newest date, in the order they were given
+    pub fn most_recent_outputs(file_names: &[String]) -> Vec<String> {
+        let Some(latest) = file_names.iter().filter_map(|name| output_date(name)).max() else {
+            return Vec::new();
+        };
+
+        file_names
+            .iter()
+            .filter(|name| output_date(name) == Some(latest))
+            .cloned()
+            .collect()
+    }
+
+    // The heading written above each plan. chrono panics partway through formatting an invalid
+    // format string, so those fall back to the default.
+    pub fn format_plan_timestamp<Tz>(now: DateTime<Tz>, fmt: &str) -> String
+    where
+        Tz: TimeZone,
+        Tz::Offset: Display,
+    {
+        let fmt = if StrftimeItems::new(fmt).any(|item| matches!(item, Item::Error)) {
+            warn!("Ignoring the invalid time format {:?}", fmt);
+            DEFAULT_TIME_FORMAT
+        } else {
+            fmt
+        };
+
+        now.format(fmt).to_string()
+    }
+
+    pub fn resolve_db_path() -> PathBuf {
+        if let Ok(path) = env::var("MEAL_PLANNER_DB") {
+            if !path.is_empty() {
+                return PathBuf::from(path);
+            }
+        }
+
+        match ProjectDirs::from("", "", "meal_planner") {
+            Some(dirs) => dirs.data_dir().join("database.db"),
+            None => PathBuf::from("database.db"),
+        }
+    }
+
+    // Drops the recipes the profile planned within the last `cooldown_days` days, or ever
+    // planned when there's no cooldown. Pinned recipes are always kept.
+    pub async fn remove_duplicate_recipes(
+        recipes: Vec<api::Recipe>,
+        cooldown_days: Option<i64>,
+        profile_id: i64,
+        pool: &SqlitePool,
+    ) -> Result<Vec<api::Recipe>, sqlx::Error> {
+        let since = match cooldown_days {
+            Some(days) => Utc::now().timestamp() - days * SECONDS_PER_DAY,
+            None => i64::MIN,
+        };
+        let used = get_recently_used_recipe_ids(since, profile_id, pool).await?;
+        let pinned = get_recipe_ids_with_status(RecipeStatus::Pinned, profile_id, pool).await?;
+
+        Ok(recipes
+            .into_iter()
+            .filter(|recipe| !used.contains(&recipe.id) || pinned.contains(&recipe.id))
+            .collect())
+    }
+
+    // Keeps the first occurrence of each recipe id
+    pub fn dedup_recipes_by_id(recipes: Vec<api::Recipe>) -> Vec<api::Recipe> {
+        let mut seen_ids: HashSet<i64> = HashSet::new();
+
+        recipes
+            .into_iter()
+            .filter(|recipe| seen_ids.insert(recipe.id))
+            .collect()
+    }
+
+    // Pantry entries are ingredient ids or names, with names matched case-insensitively against
+    // the ingredients in `components`
+    pub fn pantry_ingredient_ids(
+        pantry: &[String],
+        components: &[api::models::Component],
+    ) -> HashSet<i64> {
+        let mut ids: HashSet<i64> = HashSet::new();
+        let mut names: Vec<String> = Vec::new();
+
+        for entry in pantry {
+            match entry.trim().parse::<i64>() {
+                Ok(id) => {
+                    ids.insert(id);
+                }
+                Err(_) => names.push(entry.trim().to_lowercase()),
+            }
+        }
+
+        for component in components {
+            let name = component.ingredient.display_singular.to_lowercase();
+
+            if names.contains(&name) {
+                ids.insert(component.ingredient.id);
+            }
+        }
+
+        ids
+    }
+
+    pub fn validation_input<T>(
+        prompt: Option<&str>,
+        message_on_failure: Option<&str>,
+    ) -> Result<T, InputError>
+    where
+        T: std::str::FromStr,
+        T: std::fmt::Display,
+        T::Err: std::fmt::Debug,
+    {
+        validation_input_from(&mut io::stdin().lock(), prompt, message_on_failure)
+    }
+
+    pub fn validation_input_from<T, R>(
+        reader: &mut R,
+        prompt: Option<&str>,
+        message_on_failure: Option<&str>,
+    ) -> Result<T, InputError>
+    where
+        R: BufRead,
+        T: std::str::FromStr,
+        T: std::fmt::Display,
+        T::Err: std::fmt::Debug,
+    {
+        let prompt = prompt.unwrap_or("");
+
+        loop {
+            print!("{}", prompt);
+            io::stdout().flush()?;
+
+            let mut line = String::new();
+
+            if reader.read_line(&mut line)? == 0 {
+                return Err(InputError::Eof);
+            }
+
+            match line.trim().parse::<T>() {
+                Ok(r) => return Ok(r),
+                Err(_) => {
+                    match message_on_failure {
+                        Some(s) => eprintln!("{}", s),
+                        None => eprintln!("Your input could not be converted."),
+                    };
+                }
+            }
+        }
+    }
+
+    pub async fn get_matching_recipes(
+        recipes: Vec<api::Recipe>,
+        n_recipes: i64,
+        strategy: Strategy,
+        seed: Option<u64>,
+        profile_id: i64,
+        pool: &SqlitePool,
+    ) -> Result<Vec<api::Recipe>, sqlx::Error> {
+        let recipe_ids: Vec<i64> = recipes.iter().map(|recipe| recipe.id).collect();
+        let tags = get_tags_for_recipes(&recipe_ids, profile_id, pool).await?;
+
+        let mut scores: Vec<(api::Recipe, i64)> = recipes
+            .into_iter()
+            .map(|recipe| {
+                let recipe_score: i64 = tags[&recipe.id].iter().map(|tag| tag.likes).sum();
+                (recipe, recipe_score)
+            })
+            .collect();
+
+        if strategy == Strategy::WeightedRandom {
+            return Ok(weighted_sample(
+                scores,
+                n_recipes as usize,
+                &mut seeded_rng(seed),
+            ));
+        }
+
+        // Highest score first, with ties going to the lowest recipe id so the order is stable
+        scores.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.id.cmp(&b.0.id)));
+
+        if let Strategy::ShuffledTop(k) = strategy {
+            let k = k.min(scores.len());
+            scores[..k].shuffle(&mut seeded_rng(seed));
+        }
 
         Ok(scores
             .into_iter()
-            .map(|i| i.0)
-            .rev()
-            .take(n_recipes as usize)
-            .collect())
+            .map(|i| i.0)
+            .take(n_recipes as usize)
+            .collect())
+    }
+
+    // Weighted sampling without replacement (Efraimidis-Spirakis). Scores are shifted so the
+    // lowest one has a weight of 1, which keeps disliked recipes possible but unlikely.
+    fn weighted_sample(
+        scores: Vec<(api::Recipe, i64)>,
+        n: usize,
+        rng: &mut impl Rng,
+    ) -> Vec<api::Recipe> {
+        let min_score = scores.iter().map(|(_, score)| *score).min().unwrap_or(0);
+
+        let mut keyed: Vec<(api::Recipe, f64)> = scores
+            .into_iter()
+            .map(|(recipe, score)| {
+                let weight = (score - min_score + 1) as f64;
+                (recipe, rng.gen::<f64>().powf(1.0 / weight))
+            })
+            .collect();
+
+        keyed.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+        keyed.into_iter().map(|i| i.0).take(n).collect()
+    }
+
+    pub fn choose_recipes(
+        ranked: Vec<api::Recipe>,
+        n_recipes: usize,
+    ) -> Result<Vec<api::Recipe>, InputError> {
+        choose_recipes_from(ranked, n_recipes, &mut io::stdin().lock())
+    }
+
+    // Asks keep or skip for each recipe in ranked order until `n_recipes` are kept, so a
+    // skipped recipe is replaced by the best one not shown yet
+    pub fn choose_recipes_from<R: BufRead>(
+        ranked: Vec<api::Recipe>,
+        n_recipes: usize,
+        reader: &mut R,
+    ) -> Result<Vec<api::Recipe>, InputError> {
+        let mut kept: Vec<api::Recipe> = Vec::new();
+        let total = ranked.len();
+
+        for (i, recipe) in ranked.into_iter().enumerate() {
+            if kept.len() == n_recipes {
+                break;
+            }
+
+            let prompt = format!(
+                "[{}/{}] {} (keep or skip, enter to keep)? ",
+                i + 1,
+                total,
+                api::recipe_line(&recipe, false)
+            );
+            let choice: Choice =
+                validation_input_from(reader, Some(&prompt), Some("Please enter keep or skip."))?;
+
+            if choice == Choice::Keep {
+                kept.push(recipe);
+            }
+        }
+
+        Ok(kept)
+    }
+
+    // The same seed always gives the same picks; without one the picks come from entropy
+    fn seeded_rng(seed: Option<u64>) -> StdRng {
+        match seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        }
+    }
+
+    // Selection for when there's no database: no scoring, blacklist, or pins, just the API's
+    // order (or a random pick with the weighted strategy, since every recipe weighs the same)
+    pub fn select_recipes_stateless(
+        recipes: Vec<api::Recipe>,
+        n_recipes: i64,
+        filter: &RecipeFilter,
+        strategy: Strategy,
+        seed: Option<u64>,
+    ) -> Vec<api::Recipe> {
+        let mut recipes: Vec<api::Recipe> = recipes
+            .into_iter()
+            .filter(|recipe| filter.matches(recipe))
+            .collect();
+
+        // Without scores, the API's order stands in for them
+        match strategy {
+            Strategy::TopScored => {}
+            Strategy::WeightedRandom => recipes.shuffle(&mut seeded_rng(seed)),
+            Strategy::ShuffledTop(k) => {
+                let k = k.min(recipes.len());
+                recipes[..k].shuffle(&mut seeded_rng(seed));
+            }
+        }
+
+        recipes.truncate(n_recipes.max(0) as usize);
+        recipes
+    }
+
+    pub async fn get_matching_recipes_filtered(
+        recipes: Vec<api::Recipe>,
+        n_recipes: i64,
+        filter: &RecipeFilter,
+        strategy: Strategy,
+        seed: Option<u64>,
+        profile_id: i64,
+        pool: &SqlitePool,
+    ) -> Result<Vec<api::Recipe>, sqlx::Error> {
+        let blacklisted =
+            get_recipe_ids_with_status(RecipeStatus::Blacklisted, profile_id, pool).await?;
+        let pinned_ids = get_recipe_ids_with_status(RecipeStatus::Pinned, profile_id, pool).await?;
+
+        let (mut pinned, rest): (Vec<api::Recipe>, Vec<api::Recipe>) = recipes
+            .into_iter()
+            .filter(|recipe| filter.matches(recipe) && !blacklisted.contains(&recipe.id))
+            .partition(|recipe| pinned_ids.contains(&recipe.id));
+
+        // Pinned recipes skip scoring and take the first slots
+        pinned.truncate(n_recipes as usize);
+        let remaining = n_recipes - pinned.len() as i64;
+        pinned
+            .extend(get_matching_recipes(rest, remaining, strategy, seed, profile_id, pool).await?);
+
+        Ok(pinned)
+    }
+
+    pub mod models {
+        use std::{fmt::Display, str::FromStr};
+
+        use crate::api;
+        use chrono::NaiveDate;
+        use serde::{Deserialize, Serialize};
+        use thiserror::Error;
+
+        #[derive(Debug, Clone, PartialEq, Eq)]
+        pub struct RecipeFilter {
+            pub required_tag_ids: Vec<i64>,
+            pub max_minutes: Option<i64>,
+            // Whether recipes without a total time pass the `max_minutes` check
+            pub include_untimed: bool,
+            pub max_calories: Option<i64>,
+            // Whether recipes without a calorie count pass the `max_calories` check
+            pub include_uncounted: bool,
+            // Recipes created before this date are skipped
+            pub min_created: Option<NaiveDate>,
+            // Whether recipes without a creation date pass the `min_created` check
+            pub include_undated: bool,
+            // Recipes with fewer usable ingredients than this are skipped
+            pub min_ingredients: usize,
+            // Recipes planned within this many days are skipped instead of every recipe planned
+            // before. Needs the history in the database, so `matches` doesn't check it.
+            pub cooldown_days: Option<i64>,
+        }
+
+        impl Default for RecipeFilter {
+            fn default() -> Self {
+                RecipeFilter {
+                    required_tag_ids: Vec::new(),
+                    max_minutes: None,
+                    include_untimed: true,
+                    max_calories: None,
+                    include_uncounted: true,
+                    min_created: None,
+                    include_undated: true,
+                    min_ingredients: 1,
+                    cooldown_days: None,
+                }
+            }
+        }
+
+        impl RecipeFilter {
+            pub fn matches(&self, recipe: &api::Recipe) -> bool {
+                let has_tags = self
+                    .required_tag_ids
+                    .iter()
+                    .all(|tag_id| recipe.tags.iter().any(|tag| tag.id == *tag_id));
+
+                let fits_time = match (self.max_minutes, recipe.total_time_minutes) {
+                    (None, _) => true,
+                    (Some(max), Some(minutes)) => minutes <= max,
+                    (Some(_), None) => self.include_untimed,
+                };
+
+                let calories = recipe.nutrition.as_ref().and_then(|n| n.calories);
+                let fits_calories = match (self.max_calories, calories) {
+                    (None, _) => true,
+                    (Some(max), Some(calories)) => calories <= max,
+                    (Some(_), None) => self.include_uncounted,
+                };
+
+                let fits_created = match (self.min_created, recipe.created_date()) {
+                    (None, _) => true,
+                    (Some(min), Some(created)) => created >= min,
+                    (Some(_), None) => self.include_undated,
+                };
+
+                let has_ingredients = api::usable_ingredient_count(recipe) >= self.min_ingredients;
+
+                has_tags && fits_time && fits_calories && fits_created && has_ingredients
+            }
+        }
+
+        #[derive(Debug, Error)]
+        pub enum InputError {
+            #[error("Reached the end of the input before a valid value was entered.")]
+            Eof,
+            #[error("Failed to read input.")]
+            Io(#[from] std::io::Error),
+        }
+
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        pub enum Rating {
+            Dislike = -1,
+            None = 0,
+            Like = 1,
+            Love = 2,
+        }
+
+        impl Rating {
+            pub fn value(&self) -> i64 {
+                *self as i64
+            }
+        }
+
+        impl TryFrom<i64> for Rating {
+            type Error = i64;
+            fn try_from(value: i64) -> Result<Self, Self::Error> {
+                match value {
+                    -1 => Ok(Rating::Dislike),
+                    0 => Ok(Rating::None),
+                    1 => Ok(Rating::Like),
+                    2 => Ok(Rating::Love),
+                    _ => Err(value),
+                }
+            }
+        }
+
+        impl FromStr for Rating {
+            type Err = &'static str;
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                use Rating::*;
+
+                // -1 to 2 are shortcuts for the words, in the same order
+                if let Ok(value) = s.trim().parse::<i64>() {
+                    return Rating::try_from(value).map_err(|_| {
+                        "Please enter a number from -1 to 2, or dislike, none, like, or love."
+                    });
+                }
+
+                match s.to_lowercase().as_str() {
+                    "dislike" => Ok(Dislike),
+                    "none" => Ok(None),
+                    "like" => Ok(Like),
+                    "love" => Ok(Love),
+                    _ => Err("Please enter dislike (-1), none (0), like (1), or love (2)."),
+                }
+            }
+        }
+
+        impl Display for Rating {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                use Rating::*;
+
+                match self {
+                    Dislike => write!(f, "dislike"),
+                    None => write!(f, "none"),
+                    Like => write!(f, "like"),
+                    Love => write!(f, "love"),
+                }
+            }
+        }
+
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        pub enum OutputFormat {
+            Text,
+            Json,
+            Markdown,
+            Csv,
+            Html,
+            // One item per line, for pasting into a task manager
+            Tasks,
+        }
+
+        impl OutputFormat {
+            pub fn extension(&self) -> &'static str {
+                match self {
+                    OutputFormat::Text => "txt",
+                    OutputFormat::Json => "json",
+                    OutputFormat::Markdown => "md",
+                    OutputFormat::Csv => "csv",
+                    OutputFormat::Html => "html",
+                    OutputFormat::Tasks => "txt",
+                }
+            }
+        }
+
+        impl FromStr for OutputFormat {
+            type Err = &'static str;
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                match s.to_lowercase().as_str() {
+                    "text" | "txt" => Ok(OutputFormat::Text),
+                    "json" => Ok(OutputFormat::Json),
+                    "markdown" | "md" => Ok(OutputFormat::Markdown),
+                    "csv" => Ok(OutputFormat::Csv),
+                    "html" => Ok(OutputFormat::Html),
+                    "tasks" => Ok(OutputFormat::Tasks),
+                    _ => Err("Please enter text, json, markdown, csv, html, or tasks."),
+                }
+            }
+        }
+
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+        pub enum QuantityStyle {
+            // "1.50"
+            #[default]
+            Decimal,
+            // "1½"
+            Fraction,
+        }
+
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+        pub enum UnitSystem {
+            // Units are left the way the recipes wrote them
+            #[default]
+            AsIs,
+            // Milliliters and grams
+            Metric,
+            // Cups and ounces
+            Imperial,
+        }
+
+        impl FromStr for UnitSystem {
+            type Err = &'static str;
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                match s.to_lowercase().as_str() {
+                    "as-is" => Ok(UnitSystem::AsIs),
+                    "metric" => Ok(UnitSystem::Metric),
+                    "imperial" => Ok(UnitSystem::Imperial),
+                    _ => Err("Please enter metric, imperial, or as-is."),
+                }
+            }
+        }
+
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+        pub enum Strategy {
+            // Always the highest scoring recipes
+            #[default]
+            TopScored,
+            // Higher scoring recipes are more likely to be picked, but any recipe can be
+            WeightedRandom,
+            // Any of this many highest scoring recipes, picked at random
+            ShuffledTop(usize),
+        }
+
+        impl FromStr for Strategy {
+            type Err = &'static str;
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                match s.to_lowercase().as_str() {
+                    "top" => Ok(Strategy::TopScored),
+                    "weighted" => Ok(Strategy::WeightedRandom),
+                    _ => Err("Please enter top or weighted."),
+                }
+            }
+        }
+
+        // An answer when approving recipes one at a time
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        pub enum Choice {
+            Keep,
+            Skip,
+        }
+
+        impl FromStr for Choice {
+            type Err = &'static str;
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                match s.to_lowercase().as_str() {
+                    "keep" | "k" | "" => Ok(Choice::Keep),
+                    "skip" | "s" => Ok(Choice::Skip),
+                    _ => Err("Please enter keep or skip."),
+                }
+            }
+        }
+
+        impl Display for Choice {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                match self {
+                    Choice::Keep => write!(f, "keep"),
+                    Choice::Skip => write!(f, "skip"),
+                }
+            }
+        }
+
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        pub enum UndoOutcome {
+            Undone,
+            // The profile isn't waiting on a review, so there's no prepare run to undo
+            NothingToUndo,
+            // Some of the run's recipes were already rated and their ratings applied to the tags
+            AlreadyRated,
+        }
+
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        pub enum RecipeStatus {
+            Blacklisted = 0,
+            Pinned = 1,
+        }
+
+        impl RecipeStatus {
+            pub fn value(&self) -> i64 {
+                *self as i64
+            }
+        }
+
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+        pub enum Mode {
+            Prepare = 0,
+            Review = 1,
+        }
+
+        impl Mode {
+            pub fn value(&self) -> i64 {
+                *self as i64
+            }
+        }
+
+        impl From<i64> for Mode {
+            fn from(value: i64) -> Self {
+                match value {
+                    0 => Mode::Prepare,
+                    1 => Mode::Review,
+                    _ => panic!("`data` table contains a `mode` value other than 0 or 1"),
+                }
+            }
+        }
+    }
+
+    pub mod conversions {
+        use super::models::UnitSystem;
+        use crate::api::models::Unit;
+        use phf::{phf_map, phf_set};
+
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        pub enum Dimension {
+            Volume,
+            Mass,
+        }
+
+        // Size of each unit in milliliters (volume) or grams (mass)
+        static UNITS: phf::Map<&'static str, (Dimension, f64)> = phf_map! {
+            "milliliter" => (Dimension::Volume, 1.0),
+            "liter" => (Dimension::Volume, 1000.0),
+            "teaspoon" => (Dimension::Volume, 4.92892),
+            "tablespoon" => (Dimension::Volume, 14.7868),
+            "fluid ounce" => (Dimension::Volume, 29.5735),
+            "cup" => (Dimension::Volume, 236.588),
+            "pint" => (Dimension::Volume, 473.176),
+            "quart" => (Dimension::Volume, 946.353),
+            "gallon" => (Dimension::Volume, 3785.41),
+            "gram" => (Dimension::Mass, 1.0),
+            "kilogram" => (Dimension::Mass, 1000.0),
+            "ounce" => (Dimension::Mass, 28.3495),
+            "pound" => (Dimension::Mass, 453.592),
+        };
+
+        // Units that things are counted in. They add up, but don't convert to anything.
+        static COUNT_UNITS: phf::Set<&'static str> = phf_set! {
+            "",
+            "clove",
+            "can",
+            "slice",
+            "stalk",
+            "sprig",
+            "piece",
+            "pinch",
+            "dash",
+            "bunch",
+            "head",
+            "package",
+        };
+
+        // The smallest amount worth measuring in each unit. Unitless amounts, like eggs, and
+        // things counted whole are rounded to whole numbers.
+        static ROUNDING_STEPS: phf::Map<&'static str, f64> = phf_map! {
+            "" => 1.0,
+            "clove" => 1.0,
+            "can" => 1.0,
+            "slice" => 1.0,
+            "stalk" => 1.0,
+            "sprig" => 1.0,
+            "milliliter" => 5.0,
+            "liter" => 0.05,
+            "teaspoon" => 0.25,
+            "tablespoon" => 0.5,
+            "fluid ounce" => 0.5,
+            "cup" => 0.125,
+            "pint" => 0.25,
+            "quart" => 0.25,
+            "gallon" => 0.25,
+            "gram" => 5.0,
+            "kilogram" => 0.05,
+            "ounce" => 0.25,
+            "pound" => 0.25,
+        };
+
+        // Canonical unit each dimension is normalized to
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        pub enum BaseUnit {
+            Milliliter,
+            Gram,
+        }
+
+        impl Dimension {
+            pub fn base_unit(self) -> BaseUnit {
+                match self {
+                    Dimension::Volume => BaseUnit::Milliliter,
+                    Dimension::Mass => BaseUnit::Gram,
+                }
+            }
+        }
+
+        fn lookup(unit: &Unit) -> Option<(Dimension, f64)> {
+            UNITS.get(unit.name.to_lowercase().as_str()).copied()
+        }
+
+        pub fn dimension(unit: &Unit) -> Option<Dimension> {
+            lookup(unit).map(|(dimension, _)| dimension)
+        }
+
+        // Whether amounts in the unit can be added together
+        pub fn is_known_unit(name: &str) -> bool {
+            let name = name.to_lowercase();
+
+            UNITS.contains_key(name.as_str()) || COUNT_UNITS.contains(name.as_str())
+        }
+
+        pub fn rounding_step(unit: &Unit) -> Option<f64> {
+            ROUNDING_STEPS
+                .get(unit.name.to_lowercase().as_str())
+                .copied()
+        }
+
+        pub fn to_base(quantity: f64, unit: &Unit) -> Option<(f64, BaseUnit)> {
+            let (dimension, size) = lookup(unit)?;
+
+            Some((quantity * size, dimension.base_unit()))
+        }
+
+        // The unit each system writes a dimension in, as (name, abbreviation)
+        fn system_unit(
+            dimension: Dimension,
+            system: UnitSystem,
+        ) -> Option<(&'static str, &'static str)> {
+            match (system, dimension) {
+                (UnitSystem::AsIs, _) => None,
+                (UnitSystem::Metric, Dimension::Volume) => Some(("milliliter", "ml")),
+                (UnitSystem::Metric, Dimension::Mass) => Some(("gram", "g")),
+                (UnitSystem::Imperial, Dimension::Volume) => Some(("cup", "cup")),
+                (UnitSystem::Imperial, Dimension::Mass) => Some(("ounce", "oz")),
+            }
+        }
+
+        // `None` when the unit has no conversion or the system keeps units as they are
+        pub fn to_system(quantity: f64, unit: &Unit, system: UnitSystem) -> Option<(f64, Unit)> {
+            let (dimension, _) = lookup(unit)?;
+            let (name, abbreviation) = system_unit(dimension, system)?;
+            let target = Unit {
+                name: name.to_owned(),
+                abbreviation: abbreviation.to_owned(),
+            };
+
+            Some((convert(quantity, unit, &target)?, target))
+        }
+
+        pub fn convert(quantity: f64, from: &Unit, to: &Unit) -> Option<f64> {
+            let (from_dimension, from_size) = lookup(from)?;
+            let (to_dimension, to_size) = lookup(to)?;
+
+            if from_dimension != to_dimension {
+                return None;
+            }
+
+            Some(quantity * from_size / to_size)
+        }
+
+        #[cfg(test)]
+        mod tests {
+            use super::*;
+
+            fn unit(name: &str) -> Unit {
+                Unit {
+                    name: name.to_owned(),
+                    abbreviation: String::new(),
+                }
+            }
+
+            #[test]
+            fn cups_convert_to_tablespoons() {
+                let tablespoons = convert(1.0, &unit("cup"), &unit("tablespoon")).unwrap();
+
+                assert!((tablespoons - 16.0).abs() < 0.01);
+            }
+
+            #[test]
+            fn kilograms_convert_to_grams() {
+                assert_eq!(convert(1.5, &unit("Kilogram"), &unit("gram")), Some(1500.0));
+            }
+
+            #[test]
+            fn volume_and_mass_do_not_convert() {
+                assert_eq!(convert(100.0, &unit("milliliter"), &unit("gram")), None);
+                assert_eq!(convert(2.0, &unit("clove"), &unit("gram")), None);
+            }
+        }
+    }
+
+    pub mod categories {
+        use phf::phf_map;
+        use serde::{Deserialize, Serialize};
+        use std::fmt::Display;
+
+        #[derive(
+            Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+        )]
+        pub enum Category {
+            Produce,
+            Meat,
+            Seafood,
+            Dairy,
+            Baking,
+            Spices,
+            Pantry,
+            Beverages,
+            Other,
+        }
+
+        static KEYWORDS: phf::Map<&'static str, Category> = phf_map! {
+            "salt" => Category::Spices,
+            "pepper" => Category::Spices,
+            "cinnamon" => Category::Spices,
+            "cumin" => Category::Spices,
+            "paprika" => Category::Spices,
+            "oregano" => Category::Spices,
+            "thyme" => Category::Spices,
+            "rosemary" => Category::Spices,
+            "nutmeg" => Category::Spices,
+            "turmeric" => Category::Spices,
+            "onion" => Category::Produce,
+            "garlic" => Category::Produce,
+            "tomato" => Category::Produce,
+            "potato" => Category::Produce,
+            "carrot" => Category::Produce,
+            "celery" => Category::Produce,
+            "lettuce" => Category::Produce,
+            "spinach" => Category::Produce,
+            "kale" => Category::Produce,
+            "cabbage" => Category::Produce,
+            "broccoli" => Category::Produce,
+            "cauliflower" => Category::Produce,
+            "cucumber" => Category::Produce,
+            "zucchini" => Category::Produce,
+            "jalapeño" => Category::Produce,
+            "avocado" => Category::Produce,
+            "lemon" => Category::Produce,
+            "lime" => Category::Produce,
+            "orange" => Category::Produce,
+            "apple" => Category::Produce,
+            "banana" => Category::Produce,
+            "berry" => Category::Produce,
+            "strawberry" => Category::Produce,
+            "blueberry" => Category::Produce,
+            "raspberry" => Category::Produce,
+            "mushroom" => Category::Produce,
+            "ginger" => Category::Produce,
+            "cilantro" => Category::Produce,
+            "parsley" => Category::Produce,
+            "basil" => Category::Produce,
+            "mint" => Category::Produce,
+            "scallion" => Category::Produce,
+            "shallot" => Category::Produce,
+            "corn" => Category::Produce,
+            "pea" => Category::Produce,
+            "bean" => Category::Produce,
+            "leek" => Category::Produce,
+            "squash" => Category::Produce,
+            "pumpkin" => Category::Produce,
+            "mango" => Category::Produce,
+            "pineapple" => Category::Produce,
+            "grape" => Category::Produce,
+            "chicken" => Category::Meat,
+            "beef" => Category::Meat,
+            "pork" => Category::Meat,
+            "bacon" => Category::Meat,
+            "sausage" => Category::Meat,
+            "ham" => Category::Meat,
+            "turkey" => Category::Meat,
+            "lamb" => Category::Meat,
+            "steak" => Category::Meat,
+            "salmon" => Category::Seafood,
+            "shrimp" => Category::Seafood,
+            "tuna" => Category::Seafood,
+            "cod" => Category::Seafood,
+            "fish" => Category::Seafood,
+            "crab" => Category::Seafood,
+            "scallop" => Category::Seafood,
+            "milk" => Category::Dairy,
+            "butter" => Category::Dairy,
+            "cheese" => Category::Dairy,
+            "cream" => Category::Dairy,
+            "yogurt" => Category::Dairy,
+            "egg" => Category::Dairy,
+            "parmesan" => Category::Dairy,
+            "mozzarella" => Category::Dairy,
+            "cheddar" => Category::Dairy,
+            "buttermilk" => Category::Dairy,
+            "flour" => Category::Baking,
+            "sugar" => Category::Baking,
+            "yeast" => Category::Baking,
+            "cocoa" => Category::Baking,
+            "chocolate" => Category::Baking,
+            "vanilla" => Category::Baking,
+            "cornstarch" => Category::Baking,
+            "oil" => Category::Pantry,
+            "vinegar" => Category::Pantry,
+            "rice" => Category::Pantry,
+            "pasta" => Category::Pantry,
+            "noodle" => Category::Pantry,
+            "broth" => Category::Pantry,
+            "stock" => Category::Pantry,
+            "sauce" => Category::Pantry,
+            "honey" => Category::Pantry,
+            "syrup" => Category::Pantry,
+            "bread" => Category::Pantry,
+            "tortilla" => Category::Pantry,
+            "oat" => Category::Pantry,
+            "ketchup" => Category::Pantry,
+            "mustard" => Category::Pantry,
+            "mayonnaise" => Category::Pantry,
+            "water" => Category::Beverages,
+            "wine" => Category::Beverages,
+            "beer" => Category::Beverages,
+            "coffee" => Category::Beverages,
+            "tea" => Category::Beverages,
+            "juice" => Category::Beverages,
+            "baking powder" => Category::Baking,
+            "baking soda" => Category::Baking,
+            "brown sugar" => Category::Baking,
+            "powdered sugar" => Category::Baking,
+            "bell pepper" => Category::Produce,
+            "black pepper" => Category::Spices,
+            "red pepper flakes" => Category::Spices,
+            "chili powder" => Category::Spices,
+            "garlic powder" => Category::Spices,
+            "soy sauce" => Category::Pantry,
+            "olive oil" => Category::Pantry,
+        };
+
+        fn lookup(keyword: &str) -> Option<Category> {
+            KEYWORDS
+                .get(keyword)
+                .or_else(|| keyword.strip_suffix("es").and_then(|k| KEYWORDS.get(k)))
+                .or_else(|| keyword.strip_suffix('s').and_then(|k| KEYWORDS.get(k)))
+                .copied()
+        }
+
+        pub fn categorize(ingredient_name: &str) -> Category {
+            let name = ingredient_name.to_lowercase();
+
+            if let Some(category) = lookup(&name) {
+                return category;
+            }
+
+            let words: Vec<&str> = name
+                .split(|c: char| !c.is_alphanumeric() && c != '-')
+                .filter(|w| !w.is_empty())
+                .collect();
+
+            // Two word phrases like "baking soda" come first, then single words from the end of
+            // the name since that is usually the noun ("chicken broth" is a broth, not chicken)
+            for pair in words.windows(2).rev() {
+                if let Some(category) = lookup(&pair.join(" ")) {
+                    return category;
+                }
+            }
+
+            words
+                .iter()
+                .rev()
+                .find_map(|word| lookup(word))
+                .unwrap_or(Category::Other)
+        }
+
+        impl Display for Category {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                use Category::*;
+
+                match self {
+                    Produce => write!(f, "Produce"),
+                    Meat => write!(f, "Meat"),
+                    Seafood => write!(f, "Seafood"),
+                    Dairy => write!(f, "Dairy"),
+                    Baking => write!(f, "Baking"),
+                    Spices => write!(f, "Spices"),
+                    Pantry => write!(f, "Pantry"),
+                    Beverages => write!(f, "Beverages"),
+                    Other => write!(f, "Other"),
+                }
+            }
+        }
+
+        #[cfg(test)]
+        mod tests {
+            use super::*;
+
+            #[test]
+            fn the_last_word_decides_the_category() {
+                assert_eq!(categorize("Chicken Broth"), Category::Pantry);
+                assert_eq!(categorize("boneless chicken thighs"), Category::Meat);
+            }
+
+            #[test]
+            fn phrases_come_before_single_words() {
+                assert_eq!(categorize("1 tsp baking soda"), Category::Baking);
+            }
+
+            #[test]
+            fn unknown_ingredients_are_other() {
+                assert_eq!(categorize("xanthan gum"), Category::Other);
+            }
+        }
+    }
+
+    pub mod synonyms {
+        use crate::api::models::Ingredient;
+        use phf::phf_map;
+
+        // Every spelling of an ingredient, including the canonical one, maps to the same canonical
+        // name and id. The ids are negative so they never collide with Tasty's.
+        static SYNONYMS: phf::Map<&'static str, (&'static str, i64)> = phf_map! {
+            "green onion" => ("green onion", -1),
+            "scallion" => ("green onion", -1),
+            "spring onion" => ("green onion", -1),
+            "cilantro" => ("cilantro", -2),
+            "fresh cilantro" => ("cilantro", -2),
+            "fresh coriander" => ("cilantro", -2),
+            "chickpea" => ("chickpea", -3),
+            "garbanzo bean" => ("chickpea", -3),
+            "powdered sugar" => ("powdered sugar", -4),
+            "confectioners' sugar" => ("powdered sugar", -4),
+            "icing sugar" => ("powdered sugar", -4),
+            "eggplant" => ("eggplant", -5),
+            "aubergine" => ("eggplant", -5),
+            "zucchini" => ("zucchini", -6),
+            "courgette" => ("zucchini", -6),
+            "heavy cream" => ("heavy cream", -7),
+            "heavy whipping cream" => ("heavy cream", -7),
+            "double cream" => ("heavy cream", -7),
+        };
+
+        pub fn canonical_ingredient(ingredient: &Ingredient) -> Ingredient {
+            match SYNONYMS.get(ingredient.display_singular.to_lowercase().trim()) {
+                Some((name, id)) => Ingredient {
+                    id: *id,
+                    display_singular: (*name).to_owned(),
+                },
+                None => ingredient.clone(),
+            }
+        }
+
+        #[cfg(test)]
+        mod tests {
+            use super::*;
+
+            fn ingredient(id: i64, name: &str) -> Ingredient {
+                Ingredient {
+                    id,
+                    display_singular: name.to_owned(),
+                }
+            }
+
+            #[test]
+            fn synonyms_share_a_name_and_id() {
+                let scallion = canonical_ingredient(&ingredient(10, "Scallion"));
+                let green_onion = canonical_ingredient(&ingredient(20, "green onion"));
+                assert_eq!(scallion.display_singular, "green onion");
+                assert_eq!(scallion.id, green_onion.id);
+            }
+
+            #[test]
+            fn other_ingredients_are_unchanged() {
+                let carrot = canonical_ingredient(&ingredient(30, "carrot"));
+                assert_eq!(carrot.id, 30);
+                assert_eq!(carrot.display_singular, "carrot");
+            }
+        }
+    }
+
+    pub mod diets {
+        use std::str::FromStr;
+
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        pub enum Diet {
+            Vegetarian,
+            Vegan,
+            GlutenFree,
+            DairyFree,
+        }
+
+        impl Diet {
+            // The Tasty tag marking recipes that fit the diet
+            pub fn tag_id(self) -> i64 {
+                match self {
+                    Diet::Vegetarian => 64469,
+                    Diet::Vegan => 64468,
+                    Diet::GlutenFree => 64465,
+                    Diet::DairyFree => 64463,
+                }
+            }
+        }
+
+        impl FromStr for Diet {
+            type Err = String;
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                match s.to_lowercase().replace('-', "_").as_str() {
+                    "vegetarian" => Ok(Diet::Vegetarian),
+                    "vegan" => Ok(Diet::Vegan),
+                    "gluten_free" => Ok(Diet::GlutenFree),
+                    "dairy_free" => Ok(Diet::DairyFree),
+                    _ => Err(format!(
+                        "unknown diet \"{}\", expected vegetarian, vegan, gluten_free, or dairy_free",
+                        s
+                    )),
+                }
+            }
+        }
+
+        // Tags a recipe needs to fit every one of `diets`, without repeats
+        pub fn diet_tag_ids(diets: &[Diet]) -> Vec<i64> {
+            let mut tag_ids: Vec<i64> = diets.iter().map(|diet| diet.tag_id()).collect();
+            tag_ids.sort_unstable();
+            tag_ids.dedup();
+            tag_ids
+        }
+
+        #[cfg(test)]
+        mod tests {
+            use super::*;
+
+            #[test]
+            fn diets_parse_in_any_case_with_dashes_or_underscores() {
+                assert_eq!("Vegan".parse(), Ok(Diet::Vegan));
+                assert_eq!("vegetarian".parse(), Ok(Diet::Vegetarian));
+                assert_eq!("gluten-free".parse(), Ok(Diet::GlutenFree));
+                assert_eq!("DAIRY_FREE".parse(), Ok(Diet::DairyFree));
+                assert!("keto".parse::<Diet>().is_err());
+            }
+
+            #[test]
+            fn each_diet_tag_is_listed_once() {
+                assert_eq!(
+                    diet_tag_ids(&[Diet::Vegan, Diet::GlutenFree, Diet::Vegan]),
+                    [64465, 64468]
+                );
+                assert!(diet_tag_ids(&[]).is_empty());
+            }
+        }
+    }
+
+    pub mod substitutions {
+        use std::{collections::HashSet, fmt::Display};
+
+        use crate::api::models::Component;
+        use phf::phf_map;
+
+        // Ingredient names, as Tasty writes them, to something that can usually stand in for them
+        static SUBSTITUTIONS: phf::Map<&'static str, &'static str> = phf_map! {
+            "butter" => "olive oil",
+            "milk" => "oat milk",
+            "heavy cream" => "coconut cream",
+            "sour cream" => "greek yogurt",
+            "cream cheese" => "cashew cream",
+            "parmesan cheese" => "nutritional yeast",
+            "egg" => "flax egg",
+            "honey" => "maple syrup",
+            "peanut butter" => "sunflower seed butter",
+            "soy sauce" => "coconut aminos",
+            "all-purpose flour" => "gluten-free flour blend",
+            "breadcrumbs" => "crushed rice crackers",
+        };
+
+        #[derive(Debug, Clone, PartialEq, Eq)]
+        pub struct Substitution {
+            pub ingredient_id: i64,
+            pub ingredient: String,
+            // `None` when nothing is known to replace the ingredient
+            pub suggestion: Option<&'static str>,
+        }
+
+        impl Display for Substitution {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                match self.suggestion {
+                    Some(suggestion) => {
+                        write!(f, "Contains {} — consider {}", self.ingredient, suggestion)
+                    }
+                    None => write!(f, "Contains {}", self.ingredient),
+                }
+            }
+        }
+
+        // The longest known name that `name` ends with as a whole word, so "unsalted butter"
+        // finds "butter" but "peanut butter" keeps its own suggestion
+        fn substitute_for(name: &str) -> Option<&'static str> {
+            let name = name.trim().to_lowercase();
+
+            SUBSTITUTIONS
+                .entries()
+                .filter(|(key, _)| {
+                    name == **key
+                        || name
+                            .strip_suffix(**key)
+                            .is_some_and(|rest| rest.ends_with(' '))
+                })
+                .max_by_key(|(key, _)| key.len())
+                .map(|(_, suggestion)| *suggestion)
+        }
+
+        // One entry per avoided ingredient in `components`, in the order they first appear
+        pub fn suggest_substitutions(
+            components: &[Component],
+            avoid: &HashSet<i64>,
+        ) -> Vec<Substitution> {
+            let mut seen: HashSet<i64> = HashSet::new();
+
+            components
+                .iter()
+                .filter(|component| avoid.contains(&component.ingredient.id))
+                .filter(|component| seen.insert(component.ingredient.id))
+                .map(|component| Substitution {
+                    ingredient_id: component.ingredient.id,
+                    ingredient: component.ingredient.display_singular.clone(),
+                    suggestion: substitute_for(&component.ingredient.display_singular),
+                })
+                .collect()
+        }
+
+        #[cfg(test)]
+        mod tests {
+            use super::*;
+            use crate::api::models::Ingredient;
+
+            fn component(id: i64, name: &str) -> Component {
+                Component {
+                    ingredient: Ingredient {
+                        id,
+                        display_singular: name.to_owned(),
+                    },
+                    measurements: vec![],
+                    separate: vec![],
+                }
+            }
+
+            #[test]
+            fn the_longest_matching_name_is_suggested() {
+                assert_eq!(substitute_for("Unsalted Butter"), Some("olive oil"));
+                assert_eq!(
+                    substitute_for("peanut butter"),
+                    Some("sunflower seed butter")
+                );
+                assert_eq!(substitute_for("buttermilk"), None);
+            }
+
+            #[test]
+            fn each_avoided_ingredient_is_listed_once() {
+                let components = [
+                    component(1, "egg"),
+                    component(2, "flour"),
+                    component(1, "egg"),
+                    component(3, "saffron"),
+                ];
+                let avoid = HashSet::from([1, 3]);
+
+                let substitutions = suggest_substitutions(&components, &avoid);
+                assert_eq!(substitutions.len(), 2);
+                assert_eq!(
+                    substitutions[0].to_string(),
+                    "Contains egg — consider flax egg"
+                );
+                assert_eq!(substitutions[1].to_string(), "Contains saffron");
+            }
+        }
+    }
+
+    pub mod pricing {
+        use std::{collections::HashMap, fs, path::Path};
+
+        use crate::api::models::{ShoppingList, ShoppingMeasurement, Unit};
+        use crate::utils::conversions::convert;
+        use serde::Deserialize;
+        use thiserror::Error;
+
+        pub type PriceMap = HashMap<i64, Price>;
+
+        // What one `unit` of an ingredient costs, like 2.50 for a kilogram of flour. Amounts in
+        // other units of the same kind are converted before they're priced.
+        #[derive(Deserialize, Debug, Clone, PartialEq)]
+        pub struct Price {
+            pub price: f64,
+            // Full unit name, like "kilogram", or empty for things bought by the piece
+            #[serde(default)]
+            pub unit: String,
+        }
+
+        #[derive(Error, Debug)]
+        pub enum PricingError {
+            #[error("could not read the price file")]
+            Io(#[from] std::io::Error),
+            #[error("could not parse the price file")]
+            Json(#[from] serde_json::Error),
+        }
+
+        #[derive(Debug, Clone, Copy, PartialEq)]
+        pub struct Estimate {
+            pub total: f64,
+            // Ingredients left out of `total` because they have no known price, or an amount
+            // that can't be converted to the unit they're priced in
+            pub unpriced: usize,
+        }
+
+        pub fn load_prices(path: &Path) -> Result<PriceMap, PricingError> {
+            Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+        }
+
+        // The amount in the price's unit, from whichever of its units can be converted to it
+        fn quantity_in(m: &ShoppingMeasurement, price: &Price) -> Option<f64> {
+            let to = Unit {
+                name: price.unit.clone(),
+                abbreviation: String::new(),
+            };
+
+            std::iter::once(m).chain(&m.alternates).find_map(|m| {
+                if m.unit_name == price.unit {
+                    return Some(m.quantity);
+                }
+
+                let from = Unit {
+                    name: m.unit_name.clone(),
+                    abbreviation: m.unit.clone(),
+                };
+                convert(m.quantity, &from, &to)
+            })
+        }
+
+        pub fn estimate_cost(shopping_list: &ShoppingList, prices: &PriceMap) -> Estimate {
+            let mut total = 0.0;
+            let mut unpriced = 0;
+
+            for item in &shopping_list.items {
+                let Some(price) = prices.get(&item.ingredient_id) else {
+                    unpriced += 1;
+                    continue;
+                };
+
+                // Ingredients without a measurement count as a single unit
+                if item.measurements.is_empty() {
+                    total += price.price;
+                    continue;
+                }
+
+                match item
+                    .measurements
+                    .iter()
+                    .map(|m| quantity_in(m, price))
+                    .sum::<Option<f64>>()
+                {
+                    Some(quantity) => total += quantity * price.price,
+                    None => unpriced += 1,
+                }
+            }
+
+            Estimate { total, unpriced }
+        }
+
+        #[cfg(test)]
+        mod tests {
+            use super::*;
+            use crate::{api::models::ShoppingItem, utils::categories::Category};
+
+            fn amount(quantity: f64, unit_name: &str, unit: &str) -> ShoppingMeasurement {
+                ShoppingMeasurement {
+                    quantity,
+                    unit: unit.to_owned(),
+                    unit_name: unit_name.to_owned(),
+                    alternates: Vec::new(),
+                }
+            }
+
+            fn list(items: Vec<(i64, Vec<ShoppingMeasurement>)>) -> ShoppingList {
+                ShoppingList {
+                    items: items
+                        .into_iter()
+                        .map(|(ingredient_id, measurements)| ShoppingItem {
+                            ingredient_id,
+                            ingredient: format!("ingredient {}", ingredient_id),
+                            category: Category::Other,
+                            measurements,
+                        })
+                        .collect(),
+                    warnings: Vec::new(),
+                }
+            }
+
+            fn price(price: f64, unit: &str) -> Price {
+                Price {
+                    price,
+                    unit: unit.to_owned(),
+                }
+            }
+
+            #[test]
+            fn amounts_are_converted_to_the_priced_unit() {
+                let prices = PriceMap::from([(1, price(2.0, "kilogram"))]);
+
+                let grams =
+                    estimate_cost(&list(vec![(1, vec![amount(500.0, "gram", "g")])]), &prices);
+                let kilograms = estimate_cost(
+                    &list(vec![(1, vec![amount(1.0, "kilogram", "kg")])]),
+                    &prices,
+                );
+
+                assert!((grams.total - 1.0).abs() < 1e-9);
+                assert!((kilograms.total - 2.0).abs() < 1e-9);
+            }
+
+            #[test]
+            fn alternate_units_are_used_when_the_first_one_does_not_convert() {
+                let mut flour = amount(1.0, "cup", "cup");
+                flour.alternates.push(amount(125.0, "gram", "g"));
+                let prices = PriceMap::from([(1, price(0.01, "gram"))]);
+
+                let estimate = estimate_cost(&list(vec![(1, vec![flour])]), &prices);
+
+                assert!((estimate.total - 1.25).abs() < 1e-9);
+                assert_eq!(estimate.unpriced, 0);
+            }
+
+            #[test]
+            fn ingredients_without_a_usable_price_are_counted() {
+                let prices = PriceMap::from([(1, price(3.0, "")), (2, price(1.0, "gram"))]);
+                let shopping_list = list(vec![
+                    (1, vec![amount(2.0, "", "")]),
+                    (2, vec![amount(1.0, "cup", "cup")]),
+                    (3, vec![amount(1.0, "cup", "cup")]),
+                ]);
+
+                let estimate = estimate_cost(&shopping_list, &prices);
+
+                assert!((estimate.total - 6.0).abs() < 1e-9);
+                assert_eq!(estimate.unpriced, 2);
+            }
+
+            #[test]
+            fn price_files_name_their_unit() {
+                let prices: PriceMap = serde_json::from_str(
+                    r#"{ "1": { "price": 2.5, "unit": "kilogram" }, "2": { "price": 0.3 } }"#,
+                )
+                .unwrap();
+
+                assert_eq!(prices[&1], price(2.5, "kilogram"));
+                assert_eq!(prices[&2], price(0.3, ""));
+            }
+        }
+    }
+
+    pub mod config {
+        use directories::ProjectDirs;
+        use log::warn;
+        use serde::Deserialize;
+        use std::{
+            env, fs,
+            path::{Path, PathBuf},
+        };
+        use thiserror::Error;
+
+        #[derive(Debug, Error, PartialEq, Eq)]
+        pub enum KeyError {
+            #[error("no Tasty API key was set")]
+            Missing,
+        }
+
+        #[derive(Deserialize, Debug, Default, Clone, PartialEq)]
+        pub struct Config {
+            pub tasty_api_key: Option<String>,
+            pub page_size: Option<i64>,
+            // Staples that are always at home, by ingredient name or id
+            #[serde(default)]
+            pub pantry: Vec<String>,
+            // Ingredients to flag in the recipes file with a substitute, by ingredient name or id
+            #[serde(default)]
+            pub avoid: Vec<String>,
+            // How many weeks it takes a rating to count half as much when recomputing likes
+            pub decay_half_life_weeks: Option<f64>,
+            // Where plans are written, instead of the working directory
+            pub output_dir: Option<PathBuf>,
+            // How far `--round` may move an amount, as a fraction of it
+            pub rounding_tolerance: Option<f64>,
+            // Size of the database connection pool
+            pub max_connections: Option<u32>,
+            // strftime format for the time above each plan, like "%H:%M"
+            pub time_format: Option<String>,
+            // Fixed offset like "+02:00" to date plans in, instead of the system's time zone
+            pub utc_offset: Option<String>,
+        }
+
+        pub fn config_path() -> Option<PathBuf> {
+            ProjectDirs::from("", "", "meal_planner")
+                .map(|dirs| dirs.config_dir().join("config.toml"))
+        }
+
+        pub fn resolve_api_key() -> Result<String, KeyError> {
+            resolve_api_key_from(load_config().tasty_api_key.as_deref())
+        }
+
+        // A blank key would only be rejected by the API, so it counts as missing
+        pub fn resolve_api_key_from(key: Option<&str>) -> Result<String, KeyError> {
+            match key.map(str::trim) {
+                Some(key) if !key.is_empty() => Ok(key.to_owned()),
+                _ => Err(KeyError::Missing),
+            }
+        }
+
+        pub fn load_config() -> Config {
+            load_config_from(config_path().as_deref())
+        }
+
+        // Values from the environment take priority over the config file
+        pub fn load_config_from(path: Option<&Path>) -> Config {
+            let config = match path.and_then(|path| fs::read_to_string(path).ok()) {
+                Some(contents) => parse_config(&contents),
+                None => Config::default(),
+            };
+
+            with_env(config, |name| env::var(name).ok())
+        }
+
+        fn parse_config(contents: &str) -> Config {
+            toml::from_str(contents).unwrap_or_else(|e| {
+                warn!("Failed to parse the config file, ignoring it: {}", e);
+                Config::default()
+            })
+        }
+
+        // `var` looks up an environment variable
+        fn with_env(mut config: Config, var: impl Fn(&str) -> Option<String>) -> Config {
+            // A blank variable doesn't hide a key set in the config file
+            if let Some(key) = var("TASTY_API_KEY").filter(|key| !key.trim().is_empty()) {
+                config.tasty_api_key = Some(key);
+            }
+
+            match var("MEAL_PLANNER_PAGE_SIZE").map(|size| size.parse()) {
+                Some(Ok(size)) => config.page_size = Some(size),
+                Some(Err(e)) => warn!("Ignoring MEAL_PLANNER_PAGE_SIZE: {}", e),
+                None => {}
+            }
+
+            match var("MEAL_PLANNER_MAX_CONNECTIONS").map(|count| count.parse()) {
+                Some(Ok(count)) => config.max_connections = Some(count),
+                Some(Err(e)) => warn!("Ignoring MEAL_PLANNER_MAX_CONNECTIONS: {}", e),
+                None => {}
+            }
+
+            config
+        }
+
+        #[cfg(test)]
+        mod tests {
+            use super::*;
+
+            const FILE: &str = "tasty_api_key = \"file-key\"\npage_size = 20\n";
+
+            // An environment with only `vars` set
+            fn env<'a>(vars: &'a [(&str, &str)]) -> impl Fn(&str) -> Option<String> + 'a {
+                |name| {
+                    vars.iter()
+                        .find(|(var, _)| *var == name)
+                        .map(|(_, value)| value.to_string())
+                }
+            }
+
+            #[test]
+            fn the_file_is_used_without_environment_variables() {
+                let config = with_env(parse_config(FILE), env(&[]));
+
+                assert_eq!(config.tasty_api_key.as_deref(), Some("file-key"));
+                assert_eq!(config.page_size, Some(20));
+            }
+
+            #[test]
+            fn environment_variables_win_over_the_file() {
+                let config = with_env(
+                    parse_config(FILE),
+                    env(&[
+                        ("TASTY_API_KEY", "env-key"),
+                        ("MEAL_PLANNER_PAGE_SIZE", "10"),
+                    ]),
+                );
+
+                assert_eq!(config.tasty_api_key.as_deref(), Some("env-key"));
+                assert_eq!(config.page_size, Some(10));
+            }
+
+            #[test]
+            fn blank_or_invalid_variables_keep_the_file_values() {
+                let config = with_env(
+                    parse_config(FILE),
+                    env(&[("TASTY_API_KEY", "  "), ("MEAL_PLANNER_PAGE_SIZE", "many")]),
+                );
+
+                assert_eq!(config.tasty_api_key.as_deref(), Some("file-key"));
+                assert_eq!(config.page_size, Some(20));
+            }
+
+            #[test]
+            fn unparseable_files_are_ignored() {
+                assert_eq!(parse_config("page_size = \"many\""), Config::default());
+            }
+
+            #[test]
+            fn missing_or_blank_keys_are_missing() {
+                assert_eq!(resolve_api_key_from(None), Err(KeyError::Missing));
+                assert_eq!(resolve_api_key_from(Some("")), Err(KeyError::Missing));
+                assert_eq!(resolve_api_key_from(Some(" \t ")), Err(KeyError::Missing));
+            }
+
+            #[test]
+            fn keys_are_trimmed() {
+                assert_eq!(
+                    resolve_api_key_from(Some(" abc123\n")),
+                    Ok("abc123".to_owned())
+                );
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::database::{
+            set_tag_likes, store_recipe, store_used_recipe,
+            tests::{memory_pool, recipe},
+            DEFAULT_PROFILE_ID,
+        };
+        use models::Rating;
+
+        #[tokio::test]
+        async fn recipes_are_allowed_again_after_the_cooldown() {
+            let pool = memory_pool().await;
+            let used_at = Utc::now().timestamp() - 8 * SECONDS_PER_DAY;
+            store_used_recipe(1, used_at, DEFAULT_PROFILE_ID, &pool)
+                .await
+                .unwrap();
+
+            let remaining = |cooldown_days| {
+                remove_duplicate_recipes(
+                    vec![recipe(1, &[])],
+                    cooldown_days,
+                    DEFAULT_PROFILE_ID,
+                    &pool,
+                )
+            };
+            assert_eq!(remaining(Some(7)).await.unwrap().len(), 1);
+            assert!(remaining(Some(8)).await.unwrap().is_empty());
+            assert!(remaining(None).await.unwrap().is_empty());
+        }
+
+        #[test]
+        fn task_lists_count_as_outputs() {
+            let names: Vec<String> = [
+                "shopping-list-2024-05-01.txt",
+                "shopping-tasks-2024-05-02.txt",
+                "recipes-2024-05-02.txt",
+                "notes.txt",
+            ]
+            .iter()
+            .map(|name| name.to_string())
+            .collect();
+
+            assert_eq!(
+                most_recent_outputs(&names),
+                vec!["shopping-tasks-2024-05-02.txt", "recipes-2024-05-02.txt"]
+            );
+        }
+
+        #[test]
+        fn slash_fractions_are_divided() {
+            assert_eq!(parse_fraction("3/4"), Some(0.75));
+            assert_eq!(parse_fraction(" 1 / 2 "), Some(0.5));
+        }
+
+        #[test]
+        fn division_by_zero_is_not_a_fraction() {
+            assert_eq!(parse_fraction("1/0"), None);
+            assert_eq!(parse_fraction("3"), None);
+        }
+
+        #[test]
+        fn exhausted_input_is_an_error() {
+            let result = validation_input_from::<i64, _>(&mut &b""[..], None, None);
+
+            assert!(matches!(result, Err(models::InputError::Eof)));
+        }
+
+        #[test]
+        fn invalid_lines_are_asked_again() {
+            let result = validation_input_from::<i64, _>(&mut &b"four\n4\n"[..], None, None);
+
+            assert!(matches!(result, Ok(4)));
+        }
+
+        fn scored(scores: &[(i64, i64)]) -> Vec<(api::Recipe, i64)> {
+            scores
+                .iter()
+                .map(|(id, score)| (recipe(*id, &[]), *score))
+                .collect()
+        }
+
+        fn recipe_ids(recipes: &[api::Recipe]) -> Vec<i64> {
+            recipes.iter().map(|recipe| recipe.id).collect()
+        }
+
+        #[test]
+        fn weighted_samples_repeat_with_the_same_seed() {
+            let scores = scored(&[(1, 0), (2, 3), (3, 5), (4, 1), (5, 2)]);
+            let sample = |seed| {
+                recipe_ids(&weighted_sample(
+                    scores.clone(),
+                    3,
+                    &mut StdRng::seed_from_u64(seed),
+                ))
+            };
+
+            assert_eq!(sample(7), sample(7));
+            assert_eq!(sample(7).len(), 3);
+        }
+
+        #[test]
+        fn weighted_samples_favor_higher_scores() {
+            // Weights of 1 and 10, so the liked recipe should come first about 10 times in 11
+            let scores = scored(&[(1, 0), (2, 9)]);
+            let liked_first = (0..1000)
+                .filter(|seed| {
+                    let sample =
+                        weighted_sample(scores.clone(), 1, &mut StdRng::seed_from_u64(*seed));
+                    sample[0].id == 2
+                })
+                .count();
+
+            assert!(liked_first > 850, "picked first {} times", liked_first);
+            assert!(liked_first < 1000, "the disliked recipe was never picked");
+        }
+
+        // Gives each recipe its own tag with the given likes, so its score is those likes
+        async fn liked_recipes(likes: &[(i64, i64)], pool: &SqlitePool) -> Vec<api::Recipe> {
+            let mut recipes = Vec::new();
+            for (id, likes) in likes {
+                let tag_id = 100 + id;
+                let recipe = recipe(*id, &[(tag_id, "tag")]);
+                store_recipe(&recipe, DEFAULT_PROFILE_ID, pool)
+                    .await
+                    .unwrap();
+                set_tag_likes(tag_id, *likes, DEFAULT_PROFILE_ID, pool)
+                    .await
+                    .unwrap();
+                recipes.push(recipe);
+            }
+            recipes
+        }
+
+        #[tokio::test]
+        async fn equal_scores_are_ordered_by_recipe_id() {
+            let pool = memory_pool().await;
+            let recipes = liked_recipes(&[(3, 0), (1, 0), (5, 2), (2, 0), (4, 2)], &pool).await;
+
+            let ranked = get_matching_recipes(
+                recipes,
+                5,
+                Strategy::TopScored,
+                None,
+                DEFAULT_PROFILE_ID,
+                &pool,
+            )
+            .await
+            .unwrap();
+
+            assert_eq!(recipe_ids(&ranked), [4, 5, 1, 2, 3]);
+        }
+
+        #[test]
+        fn fractions_print_as_their_glyphs() {
+            assert_eq!(to_fraction_glyph(0.5), Some('½'));
+            assert_eq!(to_fraction_glyph(0.75), Some('¾'));
+            assert_eq!(to_fraction_glyph(2.0 / 3.0), Some('⅔'));
+            assert_eq!(to_fraction_glyph(0.875), Some('⅞'));
+            // Rounded thirds still count
+            assert_eq!(to_fraction_glyph(0.333), Some('⅓'));
+        }
+
+        #[test]
+        fn whole_numbers_and_unknown_fractions_have_no_glyph() {
+            assert_eq!(to_fraction_glyph(0.0), None);
+            assert_eq!(to_fraction_glyph(1.0), None);
+            assert_eq!(to_fraction_glyph(1.5), None);
+            assert_eq!(to_fraction_glyph(0.45), None);
+        }
+
+        #[test]
+        fn opening_files_does_nothing_in_tests() {
+            assert!(open_file(Path::new("no-such-file.txt")).is_ok());
+        }
+
+        #[tokio::test]
+        async fn the_same_seed_picks_the_same_recipes_in_the_same_order() {
+            let pool = memory_pool().await;
+            let recipes =
+                liked_recipes(&[(1, 0), (2, 3), (3, 1), (4, 5), (5, 2), (6, 0)], &pool).await;
+
+            for strategy in [Strategy::WeightedRandom, Strategy::ShuffledTop(5)] {
+                let run = || {
+                    get_matching_recipes(
+                        recipes.clone(),
+                        4,
+                        strategy,
+                        Some(42),
+                        DEFAULT_PROFILE_ID,
+                        &pool,
+                    )
+                };
+                let first = recipe_ids(&run().await.unwrap());
+                let second = recipe_ids(&run().await.unwrap());
+
+                assert_eq!(first.len(), 4);
+                assert_eq!(first, second, "{:?}", strategy);
+            }
+        }
+
+        fn ranked(ids: std::ops::RangeInclusive<i64>) -> Vec<api::Recipe> {
+            ids.map(|id| recipe(id, &[])).collect()
+        }
+
+        #[test]
+        fn skipped_recipes_are_replaced_by_the_next_ranked_one() {
+            let kept = choose_recipes_from(ranked(1..=5), 2, &mut &b"skip\n\nskip\nkeep\n"[..]);
+
+            assert_eq!(recipe_ids(&kept.unwrap()), [2, 4]);
+        }
+
+        #[test]
+        fn choosing_stops_once_enough_are_kept() {
+            // The extra answers are never read
+            let kept = choose_recipes_from(ranked(1..=5), 2, &mut &b"keep\nkeep\nskip\n"[..]);
+
+            assert_eq!(recipe_ids(&kept.unwrap()), [1, 2]);
+        }
+
+        #[test]
+        fn running_out_of_recipes_keeps_what_was_chosen() {
+            let kept = choose_recipes_from(ranked(1..=3), 3, &mut &b"skip\nkeep\nskip\n"[..]);
+
+            assert_eq!(recipe_ids(&kept.unwrap()), [2]);
+        }
+
+        #[test]
+        fn input_ending_while_choosing_is_an_error() {
+            let kept = choose_recipes_from(ranked(1..=3), 2, &mut &b"keep\n"[..]);
+
+            assert!(matches!(kept, Err(models::InputError::Eof)));
+        }
+
+        #[tokio::test]
+        async fn shuffled_top_only_shuffles_the_top_k() {
+            let pool = memory_pool().await;
+            let recipes =
+                liked_recipes(&[(1, 6), (2, 5), (3, 4), (4, 3), (5, 2), (6, 1)], &pool).await;
+
+            let mut orders = HashSet::new();
+            for seed in 0..20 {
+                let ranked = get_matching_recipes(
+                    recipes.clone(),
+                    6,
+                    Strategy::ShuffledTop(3),
+                    Some(seed),
+                    DEFAULT_PROFILE_ID,
+                    &pool,
+                )
+                .await
+                .unwrap();
+                let ids = recipe_ids(&ranked);
+
+                let mut top = ids[..3].to_vec();
+                orders.insert(top.clone());
+                top.sort();
+                assert_eq!(top, [1, 2, 3]);
+                assert_eq!(ids[3..], [4, 5, 6]);
+            }
+
+            assert!(orders.len() > 1, "the top recipes were never shuffled");
+        }
+
+        #[test]
+        fn plan_timestamps_use_the_given_format() {
+            let now = Utc.with_ymd_and_hms(2024, 5, 1, 18, 30, 0).unwrap();
+
+            assert_eq!(
+                format_plan_timestamp(now, "%Y-%m-%d %H:%M"),
+                "2024-05-01 18:30"
+            );
+            assert_eq!(format_plan_timestamp(now, "%A"), "Wednesday");
+        }
+
+        #[test]
+        fn invalid_timestamp_formats_use_the_default() {
+            let now = Utc.with_ymd_and_hms(2024, 5, 1, 18, 30, 0).unwrap();
+
+            assert_eq!(format_plan_timestamp(now, "%Q"), "06:30 pm");
+        }
+
+        #[test]
+        fn ratings_accept_numbers_from_minus_one_to_two() {
+            assert_eq!("-1".parse(), Ok(Rating::Dislike));
+            assert_eq!("0".parse(), Ok(Rating::None));
+            assert_eq!(" 1 ".parse(), Ok(Rating::Like));
+            assert_eq!("2".parse(), Ok(Rating::Love));
+            assert_eq!("Love".parse(), Ok(Rating::Love));
+        }
+
+        #[test]
+        fn ratings_reject_numbers_out_of_range() {
+            assert!("3".parse::<Rating>().is_err());
+            assert!("-2".parse::<Rating>().is_err());
+            assert!("1.5".parse::<Rating>().is_err());
+        }
+    }
+}
+
+pub mod database {
+    use crate::utils::models::{Mode, Rating, RecipeStatus, UndoOutcome};
+    use chrono::{NaiveDate, Utc};
+    pub use models::Recipe;
+    use models::{
+        Data, DataExport, DatabaseExport, PreviousRecipe, RatingRecord, RecipePref, RecipeTag,
+        Settings, ShoppingListRecord, Stats, Tag, UsedRecipe,
+    };
+    use sqlx::{
+        query, query_as, query_scalar,
+        sqlite::{SqliteConnectOptions, SqlitePoolOptions},
+        Acquire, QueryBuilder, Sqlite, SqliteConnection, SqlitePool,
+    };
+    use std::{
+        collections::{HashMap, HashSet},
+        path::Path,
+        str::FromStr,
+        time::Duration,
+    };
+
+    const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+    const ACQUIRE_TIMEOUT: Duration = Duration::from_secs(10);
+    const SECONDS_PER_WEEK: f64 = 7.0 * 24.0 * 60.0 * 60.0;
+    pub const DEFAULT_MAX_CONNECTIONS: u32 = 5;
+    // The profile used when none is chosen, which every database has
+    pub const DEFAULT_PROFILE_ID: i64 = 1;
+
+    pub async fn create_pool(
+        db_url: &str,
+        max_connections: u32,
+    ) -> Result<SqlitePool, sqlx::Error> {
+        let options = SqliteConnectOptions::from_str(db_url)?.busy_timeout(BUSY_TIMEOUT);
+
+        // A pool without connections would wait forever on the first query
+        SqlitePoolOptions::new()
+            .max_connections(max_connections.max(1))
+            .acquire_timeout(ACQUIRE_TIMEOUT)
+            .connect_with(options)
+            .await
+    }
+
+    // A read-only pool, for looking at a database without changing it. `None` when there's no
+    // database at `path` yet or it would have to be migrated first.
+    pub async fn open_existing(
+        path: &Path,
+        max_connections: u32,
+    ) -> Result<Option<SqlitePool>, sqlx::Error> {
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let pool = create_pool(
+            &format!("sqlite://{}?mode=ro", path.display()),
+            max_connections,
+        )
+        .await?;
+
+        // Databases from before migrations don't have a version to read
+        if !tables_exist(&pool).await
+            || get_schema_version(&pool).await.ok() != Some(SCHEMA_VERSION)
+        {
+            pool.close().await;
+            return Ok(None);
+        }
+
+        Ok(Some(pool))
+    }
+
+    // Whether the error came from another connection holding a lock on the database
+    pub fn is_locked_error(e: &sqlx::Error) -> bool {
+        match e {
+            sqlx::Error::PoolTimedOut => true,
+            sqlx::Error::Database(db_error) => {
+                // SQLITE_BUSY and SQLITE_LOCKED, ignoring the extended result code bits
+                matches!(
+                    db_error.code().and_then(|code| code.parse::<i32>().ok()),
+                    Some(code) if code & 0xff == 5 || code & 0xff == 6
+                )
+            }
+            _ => false,
+        }
+    }
+
+    pub async fn tables_exist(pool: &SqlitePool) -> bool {
+        query!("SELECT mode FROM data LIMIT 1")
+            .fetch_optional(pool)
+            .await
+            .unwrap_or(None)
+            .is_some()
+    }
+
+    pub async fn create_tables(pool: &SqlitePool) -> Result<(), sqlx::Error> {
+        query!(
+            "CREATE TABLE IF NOT EXISTS `tags`( \
+                `id`         INT UNSIGNED NOT NULL, \
+                `profile_id` INT UNSIGNED NOT NULL DEFAULT 1, \
+                `likes`      INT NOT NULL, \
+                `name`       VARCHAR(255) NOT NULL DEFAULT '', \
+                PRIMARY KEY(`id`, `profile_id`) \
+            )"
+        )
+        .execute(pool)
+        .await?;
+        query!(
+            "CREATE TABLE IF NOT EXISTS `recipes`( \
+                `id`   INT UNSIGNED NOT NULL PRIMARY KEY, \
+                `name` VARCHAR(255) NOT NULL, \
+                `slug` VARCHAR(255) NOT NULL DEFAULT '' \
+            )"
+        )
+        .execute(pool)
+        .await?;
+        query!(
+            "CREATE TABLE IF NOT EXISTS `previous_recipes`( \
+                `recipe_id`              INT UNSIGNED NOT NULL, \
+                `rated`                  BOOLEAN NOT NULL DEFAULT 0, \
+                `profile_id`             INT UNSIGNED NOT NULL DEFAULT 1, \
+                `used_at`                INT UNSIGNED NOT NULL DEFAULT 0, \
+                FOREIGN KEY(`recipe_id`) REFERENCES recipes(`id`) \
+            )"
+        )
+        .execute(pool)
+        .await?;
+        query!(
+            "CREATE TABLE IF NOT EXISTS `recipe_tags`( \
+                `recipe_id`              INT UNSIGNED NOT NULL, \
+                `tag_id`                 INT UNSIGNED NOT NULL, \
+                FOREIGN KEY(`recipe_id`) REFERENCES recipes(`id`) \
+            )"
+        )
+        .execute(pool)
+        .await?;
+        query!(
+            "CREATE TABLE IF NOT EXISTS `data`( \
+                `mode`           INT UNSIGNED NOT NULL DEFAULT 0, \
+                `offset`         INT UNSIGNED NOT NULL DEFAULT 0, \
+                `schema_version` INT UNSIGNED NOT NULL DEFAULT 1, \
+                `last_batch_size` INT UNSIGNED NOT NULL DEFAULT 0, \
+                `profile_id`     INT UNSIGNED NOT NULL DEFAULT 1, \
+                `default_format`   VARCHAR(16), \
+                `default_servings` INT UNSIGNED, \
+                `default_diet`     VARCHAR(32) \
+            )"
+        )
+        .execute(pool)
+        .await?;
+        query!(
+            "CREATE TABLE IF NOT EXISTS `recipe_prefs`( \
+                `recipe_id`  INT UNSIGNED NOT NULL, \
+                `profile_id` INT UNSIGNED NOT NULL DEFAULT 1, \
+                `status`     INT UNSIGNED NOT NULL, \
+                PRIMARY KEY(`recipe_id`, `profile_id`) \
+            )"
+        )
+        .execute(pool)
+        .await?;
+        query!(
+            "CREATE TABLE IF NOT EXISTS `rating_history`( \
+                `recipe_id` INT UNSIGNED NOT NULL, \
+                `tag_id`    INT UNSIGNED, \
+                `rating`    INT NOT NULL, \
+                `rated_at`  INT UNSIGNED NOT NULL, \
+                `profile_id` INT UNSIGNED NOT NULL DEFAULT 1 \
+            )"
+        )
+        .execute(pool)
+        .await?;
+        query!(
+            "CREATE TABLE IF NOT EXISTS `profiles`( \
+                `id`   INTEGER PRIMARY KEY AUTOINCREMENT, \
+                `name` VARCHAR(255) NOT NULL UNIQUE \
+            )"
+        )
+        .execute(pool)
+        .await?;
+        query!(
+            "CREATE TABLE IF NOT EXISTS `shopping_lists`( \
+                `id`         INTEGER PRIMARY KEY AUTOINCREMENT, \
+                `date`       VARCHAR(10) NOT NULL, \
+                `content`    TEXT NOT NULL, \
+                `profile_id` INT UNSIGNED NOT NULL DEFAULT 1 \
+            )"
+        )
+        .execute(pool)
+        .await?;
+        query!(
+            "CREATE TABLE IF NOT EXISTS `used_recipes`( \
+                `recipe_id`  INT UNSIGNED NOT NULL, \
+                `profile_id` INT UNSIGNED NOT NULL DEFAULT 1, \
+                `used_at`    INT UNSIGNED NOT NULL DEFAULT 0 \
+            )"
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    // Bump whenever `create_tables` changes, and add the steps that bring older databases up to
+    // the new version to `MIGRATIONS`
+    pub const SCHEMA_VERSION: i64 = 12;
+
+    enum Migration {
+        AddColumn {
+            table: &'static str,
+            column: &'static str,
+            definition: &'static str,
+        },
+        CreateTable(&'static str),
+        // For changes ALTER TABLE can't make, like a new primary key. Run in order.
+        Rebuild(&'static [&'static str]),
+    }
+
+    // Each step is tagged with the schema version it brings the database up to
+    const MIGRATIONS: &[(i64, Migration)] = &[
+        (
+            2,
+            Migration::AddColumn {
+                table: "tags",
+                column: "name",
+                definition: "VARCHAR(255) NOT NULL DEFAULT ''",
+            },
+        ),
+        (
+            3,
+            Migration::AddColumn {
+                table: "data",
+                column: "last_batch_size",
+                definition: "INT UNSIGNED NOT NULL DEFAULT 0",
+            },
+        ),
+        (
+            4,
+            Migration::CreateTable(
+                "CREATE TABLE IF NOT EXISTS `recipe_prefs`( \
+                    `recipe_id` INT UNSIGNED NOT NULL PRIMARY KEY, \
+                    `status`    INT UNSIGNED NOT NULL \
+                )",
+            ),
+        ),
+        (
+            5,
+            Migration::CreateTable(
+                "CREATE TABLE IF NOT EXISTS `rating_history`( \
+                    `recipe_id` INT UNSIGNED NOT NULL, \
+                    `tag_id`    INT UNSIGNED, \
+                    `rating`    INT NOT NULL, \
+                    `rated_at`  INT UNSIGNED NOT NULL \
+                )",
+            ),
+        ),
+        (
+            6,
+            Migration::AddColumn {
+                table: "recipes",
+                column: "slug",
+                definition: "VARCHAR(255) NOT NULL DEFAULT ''",
+            },
+        ),
+        (
+            7,
+            Migration::AddColumn {
+                table: "previous_recipes",
+                column: "rated",
+                definition: "BOOLEAN NOT NULL DEFAULT 0",
+            },
+        ),
+        (
+            8,
+            Migration::CreateTable(
+                "CREATE TABLE IF NOT EXISTS `shopping_lists`( \
+                    `id`      INTEGER PRIMARY KEY AUTOINCREMENT, \
+                    `date`    VARCHAR(10) NOT NULL, \
+                    `content` TEXT NOT NULL \
+                )",
+            ),
+        ),
+        (
+            9,
+            Migration::CreateTable(
+                "CREATE TABLE IF NOT EXISTS `profiles`( \
+                    `id`   INTEGER PRIMARY KEY AUTOINCREMENT, \
+                    `name` VARCHAR(255) NOT NULL UNIQUE \
+                )",
+            ),
+        ),
+        // Tags are keyed by profile as well, so `recipe_tags` can't reference them anymore
+        (
+            9,
+            Migration::Rebuild(&[
+                "CREATE TABLE `recipe_tags_new`( \
+                    `recipe_id`              INT UNSIGNED NOT NULL, \
+                    `tag_id`                 INT UNSIGNED NOT NULL, \
+                    FOREIGN KEY(`recipe_id`) REFERENCES recipes(`id`) \
+                )",
+                "INSERT INTO recipe_tags_new (recipe_id, tag_id) \
+                SELECT recipe_id, tag_id FROM recipe_tags",
+                "DROP TABLE recipe_tags",
+                "ALTER TABLE recipe_tags_new RENAME TO recipe_tags",
+                "CREATE TABLE `tags_new`( \
+                    `id`         INT UNSIGNED NOT NULL, \
+                    `profile_id` INT UNSIGNED NOT NULL DEFAULT 1, \
+                    `likes`      INT NOT NULL, \
+                    `name`       VARCHAR(255) NOT NULL DEFAULT '', \
+                    PRIMARY KEY(`id`, `profile_id`) \
+                )",
+                "INSERT INTO tags_new (id, profile_id, likes, name) \
+                SELECT id, 1, likes, name FROM tags",
+                "DROP TABLE tags",
+                "ALTER TABLE tags_new RENAME TO tags",
+            ]),
+        ),
+        (
+            9,
+            Migration::AddColumn {
+                table: "previous_recipes",
+                column: "profile_id",
+                definition: "INT UNSIGNED NOT NULL DEFAULT 1",
+            },
+        ),
+        (
+            9,
+            Migration::AddColumn {
+                table: "data",
+                column: "profile_id",
+                definition: "INT UNSIGNED NOT NULL DEFAULT 1",
+            },
+        ),
+        (
+            9,
+            Migration::AddColumn {
+                table: "rating_history",
+                column: "profile_id",
+                definition: "INT UNSIGNED NOT NULL DEFAULT 1",
+            },
+        ),
+        (
+            9,
+            Migration::Rebuild(&[
+                "INSERT OR IGNORE INTO profiles (id, name) VALUES (1, 'default')",
+            ]),
+        ),
+        (
+            10,
+            Migration::AddColumn {
+                table: "data",
+                column: "default_format",
+                definition: "VARCHAR(16)",
+            },
+        ),
+        (
+            10,
+            Migration::AddColumn {
+                table: "data",
+                column: "default_servings",
+                definition: "INT UNSIGNED",
+            },
+        ),
+        (
+            10,
+            Migration::AddColumn {
+                table: "data",
+                column: "default_diet",
+                definition: "VARCHAR(32)",
+            },
+        ),
+        // Recipes stored before this count as used too long ago to be cooling down
+        (
+            11,
+            Migration::AddColumn {
+                table: "previous_recipes",
+                column: "used_at",
+                definition: "INT UNSIGNED NOT NULL DEFAULT 0",
+            },
+        ),
+        // Blacklists and pins belong to a profile now
+        (
+            12,
+            Migration::Rebuild(&[
+                "CREATE TABLE `recipe_prefs_new`( \
+                    `recipe_id`  INT UNSIGNED NOT NULL, \
+                    `profile_id` INT UNSIGNED NOT NULL DEFAULT 1, \
+                    `status`     INT UNSIGNED NOT NULL, \
+                    PRIMARY KEY(`recipe_id`, `profile_id`) \
+                )",
+                "INSERT INTO recipe_prefs_new (recipe_id, profile_id, status) \
+                SELECT recipe_id, 1, status FROM recipe_prefs",
+                "DROP TABLE recipe_prefs",
+                "ALTER TABLE recipe_prefs_new RENAME TO recipe_prefs",
+            ]),
+        ),
+        (
+            12,
+            Migration::AddColumn {
+                table: "shopping_lists",
+                column: "profile_id",
+                definition: "INT UNSIGNED NOT NULL DEFAULT 1",
+            },
+        ),
+        (
+            12,
+            Migration::CreateTable(
+                "CREATE TABLE IF NOT EXISTS `used_recipes`( \
+                    `recipe_id`  INT UNSIGNED NOT NULL, \
+                    `profile_id` INT UNSIGNED NOT NULL DEFAULT 1, \
+                    `used_at`    INT UNSIGNED NOT NULL DEFAULT 0 \
+                )",
+            ),
+        ),
+        (12, Migration::Rebuild(BACKFILL_USED_RECIPES)),
+    ];
+
+    // Rebuilds `used_recipes` from the other tables, for databases and exports from before it
+    // existed. Recipes nobody is known to have planned go to the default profile.
+    const BACKFILL_USED_RECIPES: &[&str] = &[
+        "INSERT INTO used_recipes (recipe_id, profile_id, used_at) \
+        SELECT recipe_id, profile_id, used_at FROM previous_recipes",
+        "INSERT INTO used_recipes (recipe_id, profile_id, used_at) \
+        SELECT recipe_id, profile_id, MAX(rated_at) FROM rating_history \
+        WHERE NOT EXISTS (SELECT 1 FROM used_recipes \
+            WHERE used_recipes.recipe_id = rating_history.recipe_id \
+            AND used_recipes.profile_id = rating_history.profile_id) \
+        GROUP BY recipe_id, profile_id",
+        "INSERT INTO used_recipes (recipe_id, profile_id, used_at) \
+        SELECT id, 1, 0 FROM recipes \
+        WHERE id NOT IN (SELECT recipe_id FROM used_recipes)",
+    ];
+
+    // Migration statements are built at runtime, so none of them can be checked at compile time
+    async fn column_exists(
+        table: &str,
+        column: &str,
+        conn: &mut SqliteConnection,
+    ) -> Result<bool, sqlx::Error> {
+        let columns: Vec<String> =
+            sqlx::query_scalar(&format!("SELECT name FROM pragma_table_info('{}')", table))
+                .fetch_all(conn)
+                .await?;
+
+        Ok(columns.iter().any(|c| c == column))
+    }
+
+    pub async fn get_schema_version(pool: &SqlitePool) -> Result<i64, sqlx::Error> {
+        query_scalar!("SELECT schema_version FROM data LIMIT 1")
+            .fetch_one(pool)
+            .await
+    }
+
+    pub async fn run_migrations(pool: &SqlitePool) -> Result<(), sqlx::Error> {
+        let mut transaction = pool.begin().await?;
+
+        // Databases from before migrations existed are version 1
+        if !column_exists("data", "schema_version", &mut transaction).await? {
+            sqlx::query(
+                "ALTER TABLE data ADD COLUMN schema_version INT UNSIGNED NOT NULL DEFAULT 1",
+            )
+            .execute(&mut *transaction)
+            .await?;
+        }
+
+        let current_version: i64 = sqlx::query_scalar("SELECT schema_version FROM data LIMIT 1")
+            .fetch_one(&mut *transaction)
+            .await?;
+
+        for (_, migration) in MIGRATIONS
+            .iter()
+            .filter(|(version, _)| *version > current_version)
+        {
+            match migration {
+                Migration::AddColumn {
+                    table,
+                    column,
+                    definition,
+                } => {
+                    if !column_exists(table, column, &mut transaction).await? {
+                        sqlx::query(&format!(
+                            "ALTER TABLE {} ADD COLUMN {} {}",
+                            table, column, definition
+                        ))
+                        .execute(&mut *transaction)
+                        .await?;
+                    }
+                }
+                Migration::CreateTable(statement) => {
+                    sqlx::query(statement).execute(&mut *transaction).await?;
+                }
+                Migration::Rebuild(statements) => {
+                    for statement in *statements {
+                        sqlx::query(statement).execute(&mut *transaction).await?;
+                    }
+                }
+            }
+        }
+
+        if current_version < SCHEMA_VERSION {
+            query!("UPDATE data SET schema_version = $1", SCHEMA_VERSION)
+                .execute(&mut *transaction)
+                .await?;
+        }
+
+        transaction.commit().await
+    }
+
+    pub async fn populate_data_table<'c, A>(conn: A) -> Result<(), sqlx::Error>
+    where
+        A: Acquire<'c, Database = Sqlite>,
+    {
+        let mut conn = conn.acquire().await?;
+
+        query!(
+            "INSERT OR IGNORE INTO profiles (id, name) VALUES ($1, 'default')",
+            DEFAULT_PROFILE_ID
+        )
+        .execute(&mut *conn)
+        .await?;
+        query!(
+            "INSERT INTO data (schema_version, profile_id) VALUES ($1, $2)",
+            SCHEMA_VERSION,
+            DEFAULT_PROFILE_ID
+        )
+        .execute(&mut *conn)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn find_profile(name: &str, pool: &SqlitePool) -> Result<Option<i64>, sqlx::Error> {
+        query_scalar!(r#"SELECT id AS "id!" FROM profiles WHERE name = $1"#, name)
+            .fetch_optional(pool)
+            .await
+    }
+
+    // A new profile starts with no likes, an offset of 0, and its own `data` row
+    pub async fn get_or_create_profile(name: &str, pool: &SqlitePool) -> Result<i64, sqlx::Error> {
+        let mut transaction = pool.begin().await?;
+
+        query!("INSERT OR IGNORE INTO profiles (name) VALUES ($1)", name)
+            .execute(&mut *transaction)
+            .await?;
+        let profile_id = query_scalar!(r#"SELECT id AS "id!" FROM profiles WHERE name = $1"#, name)
+            .fetch_one(&mut *transaction)
+            .await?;
+        query!(
+            "INSERT INTO data (schema_version, profile_id) \
+            SELECT $1, $2 WHERE NOT EXISTS (SELECT 1 FROM data WHERE profile_id = $2)",
+            SCHEMA_VERSION,
+            profile_id
+        )
+        .execute(&mut *transaction)
+        .await?;
+
+        transaction.commit().await?;
+
+        Ok(profile_id)
+    }
+
+    pub async fn get_mode(profile_id: i64, pool: &SqlitePool) -> Result<Mode, sqlx::Error> {
+        let data = query_as!(
+            Data,
+            "SELECT mode, offset FROM data WHERE profile_id = $1 LIMIT 1",
+            profile_id
+        )
+        .fetch_one(pool)
+        .await?;
+
+        Ok(data.mode)
+    }
+
+    pub async fn get_offset(profile_id: i64, pool: &SqlitePool) -> Result<i64, sqlx::Error> {
+        let data = query_as!(
+            Data,
+            "SELECT mode, offset FROM data WHERE profile_id = $1 LIMIT 1",
+            profile_id
+        )
+        .fetch_one(pool)
+        .await?;
+
+        Ok(data.offset)
+    }
+
+    // Previous recipes still waiting for a rating, so an interrupted review can pick up again
+    pub async fn get_unrated_previous_recipes(
+        profile_id: i64,
+        pool: &SqlitePool,
+    ) -> Result<Vec<Recipe>, sqlx::Error> {
+        query_as!(
+            Recipe,
+            "SELECT recipes.id, recipes.name, recipes.slug FROM recipes \
+            INNER JOIN previous_recipes ON recipes.id = previous_recipes.recipe_id \
+            WHERE previous_recipes.rated = 0 AND previous_recipes.profile_id = $1",
+            profile_id
+        )
+        .fetch_all(pool)
+        .await
+    }
+
+    pub async fn get_previous_recipes(
+        profile_id: i64,
+        pool: &SqlitePool,
+    ) -> Result<Vec<Recipe>, sqlx::Error> {
+        query_as!(
+            Recipe,
+            "SELECT recipes.id, recipes.name, recipes.slug FROM recipes \
+            INNER JOIN previous_recipes ON recipes.id = previous_recipes.recipe_id \
+            WHERE previous_recipes.profile_id = $1",
+            profile_id
+        )
+        .fetch_all(pool)
+        .await
+    }
+
+    // Tags the profile has never stored are left out
+    pub async fn get_recipe_tags(
+        recipe_id: i64,
+        profile_id: i64,
+        pool: &SqlitePool,
+    ) -> Result<Vec<Tag>, sqlx::Error> {
+        query_as!(
+            Tag,
+            "SELECT tags.id, tags.profile_id, tags.likes, tags.name FROM recipe_tags \
+            INNER JOIN tags ON tags.id = recipe_tags.tag_id AND tags.profile_id = $2 \
+            WHERE recipe_tags.recipe_id = $1",
+            recipe_id,
+            profile_id
+        )
+        .fetch_all(pool)
+        .await
+    }
+
+    // Every recipe's tags from one query, with an empty list for recipes that have none
+    pub async fn get_tags_for_recipes(
+        recipe_ids: &[i64],
+        profile_id: i64,
+        pool: &SqlitePool,
+    ) -> Result<HashMap<i64, Vec<Tag>>, sqlx::Error> {
+        let mut tags: HashMap<i64, Vec<Tag>> =
+            recipe_ids.iter().map(|id| (*id, Vec::new())).collect();
+
+        // `IN ()` isn't valid SQL
+        if recipe_ids.is_empty() {
+            return Ok(tags);
+        }
+
+        let mut builder = QueryBuilder::<Sqlite>::new(
+            "SELECT recipe_tags.recipe_id, tags.id, tags.profile_id, tags.likes, tags.name \
+            FROM recipe_tags \
+            INNER JOIN tags ON tags.id = recipe_tags.tag_id AND tags.profile_id = ",
+        );
+        builder.push_bind(profile_id);
+        builder.push(" WHERE recipe_tags.recipe_id IN (");
+        let mut ids = builder.separated(", ");
+        for recipe_id in recipe_ids {
+            ids.push_bind(*recipe_id);
+        }
+        ids.push_unseparated(")");
+
+        let rows: Vec<(i64, i64, i64, i64, String)> =
+            builder.build_query_as().fetch_all(pool).await?;
+
+        for (recipe_id, id, profile_id, likes, name) in rows {
+            tags.entry(recipe_id).or_default().push(Tag {
+                id,
+                profile_id,
+                likes,
+                name,
+            });
+        }
+
+        Ok(tags)
+    }
+
+    // Ids in `among` that are tagged with `tag_id`, in the order given
+    pub async fn recipes_with_tag(
+        tag_id: i64,
+        among: &[i64],
+        pool: &SqlitePool,
+    ) -> Result<Vec<i64>, sqlx::Error> {
+        let tagged: HashSet<i64> = query_scalar!(
+            "SELECT recipe_id FROM recipe_tags WHERE tag_id = $1",
+            tag_id
+        )
+        .fetch_all(pool)
+        .await?
+        .into_iter()
+        .collect();
+
+        Ok(among
+            .iter()
+            .copied()
+            .filter(|id| tagged.contains(id))
+            .collect())
+    }
+
+    pub async fn update_tag_likes<'c, A>(
+        id: i64,
+        value: i64,
+        profile_id: i64,
+        conn: A,
+    ) -> Result<(), sqlx::Error>
+    where
+        A: Acquire<'c, Database = Sqlite>,
+    {
+        let mut conn = conn.acquire().await?;
+        query!(
+            "UPDATE tags SET likes = likes + $1 WHERE id = $2 AND profile_id = $3",
+            value,
+            id,
+            profile_id
+        )
+        .execute(&mut *conn)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn get_all_tags(profile_id: i64, pool: &SqlitePool) -> Result<Vec<Tag>, sqlx::Error> {
+        query_as!(
+            Tag,
+            "SELECT id, profile_id, likes, name FROM tags WHERE profile_id = $1 \
+            ORDER BY likes DESC, id",
+            profile_id
+        )
+        .fetch_all(pool)
+        .await
+    }
+
+    // `tag_id` is set when a single tag was rated differently from the rest of the recipe
+    pub async fn record_rating<'c, A>(
+        recipe_id: i64,
+        tag_id: Option<i64>,
+        rating: Rating,
+        profile_id: i64,
+        conn: A,
+    ) -> Result<(), sqlx::Error>
+    where
+        A: Acquire<'c, Database = Sqlite>,
+    {
+        let mut conn = conn.acquire().await?;
+        let value = rating.value();
+        let rated_at = Utc::now().timestamp();
+        query!(
+            "INSERT INTO rating_history (recipe_id, tag_id, rating, rated_at, profile_id) \
+            VALUES ($1, $2, $3, $4, $5)",
+            recipe_id,
+            tag_id,
+            value,
+            rated_at,
+            profile_id
+        )
+        .execute(&mut *conn)
+        .await?;
+
+        Ok(())
+    }
+
+    // Applies one recipe's rating to its tags, with `tag_ratings` holding the rating each tag
+    // ends up with, and marks the recipe as rated
+    pub async fn apply_recipe_rating(
+        recipe_id: i64,
+        rating: Rating,
+        tag_ratings: &[(i64, Rating)],
+        profile_id: i64,
+        pool: &SqlitePool,
+    ) -> Result<(), sqlx::Error> {
+        let mut transaction = pool.begin().await?;
+
+        record_rating(recipe_id, None, rating, profile_id, &mut *transaction).await?;
+
+        for (tag_id, tag_rating) in tag_ratings {
+            if *tag_rating != rating {
+                record_rating(
+                    recipe_id,
+                    Some(*tag_id),
+                    *tag_rating,
+                    profile_id,
+                    &mut *transaction,
+                )
+                .await?;
+            }
+
+            update_tag_likes(*tag_id, tag_rating.value(), profile_id, &mut *transaction).await?;
+        }
+
+        query!(
+            "UPDATE previous_recipes SET rated = 1 WHERE recipe_id = $1 AND profile_id = $2",
+            recipe_id,
+            profile_id
+        )
+        .execute(&mut *transaction)
+        .await?;
+
+        transaction.commit().await
+    }
+
+    // Rebuilds every tag's likes from `rating_history`, dropping any manual edits. A tag rated
+    // on its own uses that rating instead of its recipe's.
+    //
+    // With a half-life, each rating is weighted by 0.5^(age_in_weeks / half_life) and the
+    // weighted sum is rounded, so recent tastes outweigh old ones.
+    pub async fn recompute_tag_likes(
+        half_life_weeks: Option<f64>,
+        profile_id: i64,
+        pool: &SqlitePool,
+    ) -> Result<(), sqlx::Error> {
+        if let Some(half_life) = half_life_weeks.filter(|weeks| *weeks > 0.0) {
+            return recompute_decayed_tag_likes(half_life, profile_id, pool).await;
+        }
+
+        query!(
+            "UPDATE tags SET likes = COALESCE(( \
+                SELECT SUM(COALESCE(tag_rating.rating, recipe_rating.rating)) \
+                FROM rating_history AS recipe_rating \
+                INNER JOIN recipe_tags ON recipe_tags.recipe_id = recipe_rating.recipe_id \
+                LEFT JOIN rating_history AS tag_rating \
+                    ON tag_rating.recipe_id = recipe_rating.recipe_id \
+                    AND tag_rating.tag_id = recipe_tags.tag_id \
+                    AND tag_rating.profile_id = recipe_rating.profile_id \
+                WHERE recipe_rating.tag_id IS NULL AND recipe_tags.tag_id = tags.id \
+                    AND recipe_rating.profile_id = tags.profile_id \
+            ), 0) WHERE profile_id = $1",
+            profile_id
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn recompute_decayed_tag_likes(
+        half_life_weeks: f64,
+        profile_id: i64,
+        pool: &SqlitePool,
+    ) -> Result<(), sqlx::Error> {
+        let contributions = query!(
+            "SELECT recipe_tags.tag_id AS \"tag_id!\", \
+                COALESCE(tag_rating.rating, recipe_rating.rating) AS \"rating!: i64\", \
+                COALESCE(tag_rating.rated_at, recipe_rating.rated_at) AS \"rated_at!: i64\" \
+            FROM rating_history AS recipe_rating \
+            INNER JOIN recipe_tags ON recipe_tags.recipe_id = recipe_rating.recipe_id \
+            LEFT JOIN rating_history AS tag_rating \
+                ON tag_rating.recipe_id = recipe_rating.recipe_id \
+                AND tag_rating.tag_id = recipe_tags.tag_id \
+                AND tag_rating.profile_id = recipe_rating.profile_id \
+            WHERE recipe_rating.tag_id IS NULL AND recipe_rating.profile_id = $1",
+            profile_id
+        )
+        .fetch_all(pool)
+        .await?;
+
+        let now = Utc::now().timestamp();
+        let mut likes: HashMap<i64, f64> = HashMap::new();
+
+        for row in contributions {
+            let age_weeks = (now - row.rated_at).max(0) as f64 / SECONDS_PER_WEEK;
+            *likes.entry(row.tag_id).or_default() +=
+                row.rating as f64 * decay_weight(age_weeks, half_life_weeks);
+        }
+
+        let mut tx = pool.begin().await?;
+
+        query!(
+            "UPDATE tags SET likes = 0 WHERE profile_id = $1",
+            profile_id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        for (tag_id, likes) in likes {
+            let likes = likes.round() as i64;
+            query!(
+                "UPDATE tags SET likes = $1 WHERE id = $2 AND profile_id = $3",
+                likes,
+                tag_id,
+                profile_id
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await
+    }
+
+    fn decay_weight(age_weeks: f64, half_life_weeks: f64) -> f64 {
+        0.5_f64.powf(age_weeks / half_life_weeks)
+    }
+
+    pub async fn rating_stats(profile_id: i64, pool: &SqlitePool) -> Result<Stats, sqlx::Error> {
+        let counts = query!(
+            "SELECT rating, COUNT(*) AS count FROM rating_history \
+            WHERE tag_id IS NULL AND profile_id = $1 \
+            GROUP BY rating ORDER BY rating DESC",
+            profile_id
+        )
+        .fetch_all(pool)
+        .await?;
+        let top_tags = query_as!(
+            Tag,
+            "SELECT id, profile_id, likes, name FROM tags WHERE likes > 0 AND profile_id = $1 \
+            ORDER BY likes DESC, id LIMIT 5",
+            profile_id
+        )
+        .fetch_all(pool)
+        .await?;
+        let bottom_tags = query_as!(
+            Tag,
+            "SELECT id, profile_id, likes, name FROM tags WHERE likes < 0 AND profile_id = $1 \
+            ORDER BY likes, id LIMIT 5",
+            profile_id
+        )
+        .fetch_all(pool)
+        .await?;
+
+        let rating_counts: Vec<(Rating, i64)> = counts
+            .into_iter()
+            .filter_map(|row| Some((Rating::try_from(row.rating).ok()?, row.count)))
+            .collect();
+
+        Ok(Stats {
+            rated_recipes: rating_counts.iter().map(|(_, count)| count).sum(),
+            rating_counts,
+            top_tags,
+            bottom_tags,
+        })
+    }
+
+    pub async fn set_tag_likes(
+        id: i64,
+        value: i64,
+        profile_id: i64,
+        pool: &SqlitePool,
+    ) -> Result<(), sqlx::Error> {
+        query!(
+            "UPDATE tags SET likes = $1 WHERE id = $2 AND profile_id = $3",
+            value,
+            id,
+            profile_id
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn delete_previous_recipes(
+        profile_id: i64,
+        pool: &SqlitePool,
+    ) -> Result<(), sqlx::Error> {
+        query!(
+            "DELETE FROM previous_recipes WHERE profile_id = $1",
+            profile_id
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn set_mode<'c, A>(mode: Mode, profile_id: i64, conn: A) -> Result<(), sqlx::Error>
+    where
+        A: Acquire<'c, Database = Sqlite>,
+    {
+        let mut conn = conn.acquire().await?;
+        let value = mode.value();
+        query!(
+            "UPDATE data SET mode = $1 WHERE profile_id = $2",
+            value,
+            profile_id
+        )
+        .execute(&mut *conn)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn set_recipe_status(
+        recipe_id: i64,
+        status: RecipeStatus,
+        profile_id: i64,
+        pool: &SqlitePool,
+    ) -> Result<(), sqlx::Error> {
+        let value = status.value();
+        query!(
+            "INSERT INTO recipe_prefs (recipe_id, profile_id, status) VALUES ($1, $2, $3) \
+            ON CONFLICT(recipe_id, profile_id) DO UPDATE SET status = excluded.status",
+            recipe_id,
+            profile_id,
+            value
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn blacklist_recipe(
+        recipe_id: i64,
+        profile_id: i64,
+        pool: &SqlitePool,
+    ) -> Result<(), sqlx::Error> {
+        set_recipe_status(recipe_id, RecipeStatus::Blacklisted, profile_id, pool).await
+    }
+
+    pub async fn pin_recipe(
+        recipe_id: i64,
+        profile_id: i64,
+        pool: &SqlitePool,
+    ) -> Result<(), sqlx::Error> {
+        set_recipe_status(recipe_id, RecipeStatus::Pinned, profile_id, pool).await
+    }
+
+    pub async fn get_recipe_ids_with_status(
+        status: RecipeStatus,
+        profile_id: i64,
+        pool: &SqlitePool,
+    ) -> Result<Vec<i64>, sqlx::Error> {
+        let value = status.value();
+        query_scalar!(
+            "SELECT recipe_id FROM recipe_prefs WHERE status = $1 AND profile_id = $2",
+            value,
+            profile_id
+        )
+        .fetch_all(pool)
+        .await
+    }
+
+    // Recipes planned since `since`, a Unix timestamp
+    pub async fn get_recently_used_recipe_ids(
+        since: i64,
+        profile_id: i64,
+        pool: &SqlitePool,
+    ) -> Result<HashSet<i64>, sqlx::Error> {
+        let ids = query_scalar!(
+            "SELECT recipe_id FROM used_recipes WHERE profile_id = $1 AND used_at >= $2",
+            profile_id,
+            since
+        )
+        .fetch_all(pool)
+        .await?;
+
+        Ok(ids.into_iter().collect())
+    }
+
+    // Case-insensitive substring match on the names of recipes the profile has planned
+    pub async fn search_recipes(
+        query: &str,
+        profile_id: i64,
+        pool: &SqlitePool,
+    ) -> Result<Vec<Recipe>, sqlx::Error> {
+        let pattern = format!(
+            "%{}%",
+            query
+                .replace('\\', "\\\\")
+                .replace('%', "\\%")
+                .replace('_', "\\_")
+        );
+
+        query_as!(
+            Recipe,
+            "SELECT id, name, slug FROM recipes WHERE name LIKE $1 ESCAPE '\\' \
+            AND id IN (SELECT recipe_id FROM used_recipes WHERE profile_id = $2) ORDER BY name",
+            pattern,
+            profile_id
+        )
+        .fetch_all(pool)
+        .await
+    }
+
+    // Whether the profile has ever planned the recipe
+    pub async fn recipe_used(
+        recipe_id: i64,
+        profile_id: i64,
+        pool: &SqlitePool,
+    ) -> Result<bool, sqlx::Error> {
+        Ok(query!(
+            "SELECT recipe_id FROM used_recipes WHERE recipe_id = $1 AND profile_id = $2 LIMIT 1",
+            recipe_id,
+            profile_id
+        )
+        .fetch_optional(pool)
+        .await?
+        .is_some())
+    }
+
+    pub async fn store_tag<'c, A>(
+        tag_id: i64,
+        name: &str,
+        profile_id: i64,
+        conn: A,
+    ) -> Result<(), sqlx::Error>
+    where
+        A: Acquire<'c, Database = Sqlite>,
+    {
+        let mut conn = conn.acquire().await?;
+        query!(
+            "INSERT INTO tags (id, profile_id, likes, name) VALUES ($1, $2, 0, $3) \
+            ON CONFLICT(id, profile_id) DO UPDATE SET name = excluded.name",
+            tag_id,
+            profile_id,
+            name
+        )
+        .execute(&mut *conn)
+        .await?;
+
+        Ok(())
+    }
+    pub async fn store_recipe_tag_relationship<'c, A>(
+        recipe_id: i64,
+        tag: &crate::api::models::Tag,
+        profile_id: i64,
+        conn: A,
+    ) -> Result<(), sqlx::Error>
+    where
+        A: Acquire<'c, Database = Sqlite>,
+    {
+        let mut conn = conn.acquire().await?;
+        store_tag(tag.id, &tag.display_name, profile_id, &mut *conn).await?;
+        let tag_id = tag.id;
+
+        // Recipes are shared between profiles, so another profile may have stored this already
+        query!(
+            "INSERT INTO recipe_tags (recipe_id, tag_id) SELECT $1, $2 \
+            WHERE NOT EXISTS (SELECT 1 FROM recipe_tags WHERE recipe_id = $1 AND tag_id = $2)",
+            recipe_id,
+            tag_id
+        )
+        .execute(&mut *conn)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn store_recipe<'c, A>(
+        recipe: &crate::api::Recipe,
+        profile_id: i64,
+        conn: A,
+    ) -> Result<(), sqlx::Error>
+    where
+        A: Acquire<'c, Database = Sqlite>,
+    {
+        let mut conn = conn.acquire().await?;
+        query!(
+            "INSERT OR IGNORE INTO recipes (id, name, slug) VALUES ($1, $2, $3)",
+            recipe.id,
+            recipe.name,
+            recipe.slug,
+        )
+        .execute(&mut *conn)
+        .await?;
+
+        for tag in &recipe.tags {
+            store_recipe_tag_relationship(recipe.id, tag, profile_id, &mut *conn).await?;
+        }
+
+        Ok(())
+    }
+
+    // `used_at` is a Unix timestamp in seconds
+    pub async fn store_previous_recipe<'c, A>(
+        recipe: &crate::api::Recipe,
+        used_at: i64,
+        profile_id: i64,
+        conn: A,
+    ) -> Result<(), sqlx::Error>
+    where
+        A: Acquire<'c, Database = Sqlite>,
+    {
+        let mut conn = conn.acquire().await?;
+        query!(
+            "INSERT INTO previous_recipes (recipe_id, profile_id, used_at) VALUES ($1, $2, $3)",
+            recipe.id,
+            profile_id,
+            used_at
+        )
+        .execute(&mut *conn)
+        .await?;
+
+        Ok(())
+    }
+
+    // `used_at` is a Unix timestamp in seconds
+    pub async fn store_used_recipe<'c, A>(
+        recipe_id: i64,
+        used_at: i64,
+        profile_id: i64,
+        conn: A,
+    ) -> Result<(), sqlx::Error>
+    where
+        A: Acquire<'c, Database = Sqlite>,
+    {
+        let mut conn = conn.acquire().await?;
+        query!(
+            "INSERT INTO used_recipes (recipe_id, profile_id, used_at) VALUES ($1, $2, $3)",
+            recipe_id,
+            profile_id,
+            used_at
+        )
+        .execute(&mut *conn)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn increment_offset<'c, A>(
+        n: i64,
+        profile_id: i64,
+        conn: A,
+    ) -> Result<(), sqlx::Error>
+    where
+        A: Acquire<'c, Database = Sqlite>,
+    {
+        let mut conn = conn.acquire().await?;
+        query!(
+            "UPDATE data SET offset = offset+$1, last_batch_size = $1 WHERE profile_id = $2",
+            n,
+            profile_id
+        )
+        .execute(&mut *conn)
+        .await?;
+
+        Ok(())
+    }
+
+    // Stores everything a prepare run changes in one transaction, so an error partway through
+    // leaves the database as it was
+    pub async fn save_prepare_run(
+        recipes: &[crate::api::Recipe],
+        offset_consumed: i64,
+        date: NaiveDate,
+        shopping_list: &str,
+        profile_id: i64,
+        pool: &SqlitePool,
+    ) -> Result<(), sqlx::Error> {
+        let mut transaction = pool.begin().await?;
+        let used_at = Utc::now().timestamp();
+
+        for recipe in recipes {
+            store_recipe(recipe, profile_id, &mut *transaction).await?;
+            store_previous_recipe(recipe, used_at, profile_id, &mut *transaction).await?;
+            store_used_recipe(recipe.id, used_at, profile_id, &mut *transaction).await?;
+        }
+
+        store_shopping_list(date, shopping_list, profile_id, &mut *transaction).await?;
+        increment_offset(offset_consumed, profile_id, &mut *transaction).await?;
+        set_mode(Mode::Review, profile_id, &mut *transaction).await?;
+
+        transaction.commit().await
+    }
+
+    // Also forgets the last batch, since undoing it would no longer land on a meaningful offset
+    pub async fn set_offset(
+        value: i64,
+        profile_id: i64,
+        pool: &SqlitePool,
+    ) -> Result<(), sqlx::Error> {
+        query!(
+            "UPDATE data SET offset = $1, last_batch_size = 0 WHERE profile_id = $2",
+            value,
+            profile_id
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn get_settings(profile_id: i64, pool: &SqlitePool) -> Result<Settings, sqlx::Error> {
+        query_as!(
+            Settings,
+            "SELECT default_format, default_servings, default_diet FROM data \
+            WHERE profile_id = $1 LIMIT 1",
+            profile_id
+        )
+        .fetch_one(pool)
+        .await
+    }
+
+    // `None` clears the setting. Values are checked by the caller.
+    pub async fn set_default_format(
+        value: Option<&str>,
+        profile_id: i64,
+        pool: &SqlitePool,
+    ) -> Result<(), sqlx::Error> {
+        query!(
+            "UPDATE data SET default_format = $1 WHERE profile_id = $2",
+            value,
+            profile_id
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn set_default_servings(
+        value: Option<i64>,
+        profile_id: i64,
+        pool: &SqlitePool,
+    ) -> Result<(), sqlx::Error> {
+        query!(
+            "UPDATE data SET default_servings = $1 WHERE profile_id = $2",
+            value,
+            profile_id
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn set_default_diet(
+        value: Option<&str>,
+        profile_id: i64,
+        pool: &SqlitePool,
+    ) -> Result<(), sqlx::Error> {
+        query!(
+            "UPDATE data SET default_diet = $1 WHERE profile_id = $2",
+            value,
+            profile_id
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn export_all(pool: &SqlitePool) -> Result<DatabaseExport, sqlx::Error> {
+        let tags = query_as!(
+            Tag,
+            "SELECT id, profile_id, likes, name FROM tags ORDER BY profile_id, id"
+        )
+        .fetch_all(pool)
+        .await?;
+        let recipes = query_as!(Recipe, "SELECT id, name, slug FROM recipes ORDER BY id")
+            .fetch_all(pool)
+            .await?;
+        let recipe_tags = query_as!(
+            RecipeTag,
+            "SELECT recipe_id, tag_id FROM recipe_tags ORDER BY recipe_id, tag_id"
+        )
+        .fetch_all(pool)
+        .await?;
+        let previous_recipes = query_as!(
+            PreviousRecipe,
+            r#"SELECT recipe_id, rated AS "rated: bool", profile_id, used_at FROM previous_recipes
+            ORDER BY profile_id, recipe_id"#
+        )
+        .fetch_all(pool)
+        .await?;
+        let recipe_prefs = query_as!(
+            RecipePref,
+            "SELECT recipe_id, profile_id, status FROM recipe_prefs ORDER BY profile_id, recipe_id"
+        )
+        .fetch_all(pool)
+        .await?;
+        let rating_history = query_as!(
+            RatingRecord,
+            "SELECT recipe_id, tag_id, rating, rated_at, profile_id FROM rating_history \
+            ORDER BY rowid"
+        )
+        .fetch_all(pool)
+        .await?;
+        let shopping_lists = query_as!(
+            ShoppingListRecord,
+            "SELECT date, content, profile_id FROM shopping_lists ORDER BY id"
+        )
+        .fetch_all(pool)
+        .await?;
+        let used_recipes = query_as!(
+            UsedRecipe,
+            "SELECT recipe_id, profile_id, used_at FROM used_recipes ORDER BY rowid"
+        )
+        .fetch_all(pool)
+        .await?;
+        let data = query!(
+            "SELECT mode, offset, last_batch_size, profile_id, \
+            default_format, default_servings, default_diet FROM data ORDER BY profile_id"
+        )
+        .fetch_all(pool)
+        .await?;
+        let names: HashMap<i64, String> = query!("SELECT id, name FROM profiles")
+            .fetch_all(pool)
+            .await?
+            .into_iter()
+            .map(|row| (row.id, row.name))
+            .collect();
+
+        let mut data: Vec<DataExport> = data
+            .into_iter()
+            .map(|row| DataExport {
+                mode: Mode::from(row.mode),
+                offset: row.offset,
+                last_batch_size: row.last_batch_size,
+                profile_id: row.profile_id,
+                profile_name: names.get(&row.profile_id).cloned().unwrap_or_default(),
+                settings: Settings {
+                    default_format: row.default_format,
+                    default_servings: row.default_servings,
+                    default_diet: row.default_diet,
+                },
+            })
+            .collect();
+        let default_position = data
+            .iter()
+            .position(|row| row.profile_id == DEFAULT_PROFILE_ID)
+            .ok_or(sqlx::Error::RowNotFound)?;
+        let default_data = data.remove(default_position);
+
+        Ok(DatabaseExport {
+            tags,
+            recipes,
+            recipe_tags,
+            previous_recipes,
+            recipe_prefs,
+            rating_history,
+            shopping_lists,
+            used_recipes,
+            data: default_data,
+            profiles: data,
+        })
+    }
+
+    // Empties every table except `data` and `profiles`. Rows that reference other tables go first.
+    async fn clear_tables(conn: &mut SqliteConnection) -> Result<(), sqlx::Error> {
+        query!("DELETE FROM previous_recipes")
+            .execute(&mut *conn)
+            .await?;
+        query!("DELETE FROM used_recipes")
+            .execute(&mut *conn)
+            .await?;
+        query!("DELETE FROM recipe_tags")
+            .execute(&mut *conn)
+            .await?;
+        query!("DELETE FROM recipes").execute(&mut *conn).await?;
+        query!("DELETE FROM tags").execute(&mut *conn).await?;
+        query!("DELETE FROM recipe_prefs")
+            .execute(&mut *conn)
+            .await?;
+        query!("DELETE FROM shopping_lists")
+            .execute(&mut *conn)
+            .await?;
+        query!("DELETE FROM rating_history")
+            .execute(&mut *conn)
+            .await?;
+
+        Ok(())
+    }
+
+    // Wipes everything, leaving the database as if it had just been created
+    pub async fn reset_all(pool: &SqlitePool) -> Result<(), sqlx::Error> {
+        let mut transaction = pool.begin().await?;
+
+        clear_tables(&mut transaction).await?;
+        query!("DELETE FROM data")
+            .execute(&mut *transaction)
+            .await?;
+        query!("DELETE FROM profiles")
+            .execute(&mut *transaction)
+            .await?;
+        populate_data_table(&mut *transaction).await?;
+
+        transaction.commit().await
+    }
+
+    // Replaces everything in the database with `export`
+    pub async fn import_all(export: &DatabaseExport, pool: &SqlitePool) -> Result<(), sqlx::Error> {
+        let mut transaction = pool.begin().await?;
+
+        // Rows that reference other tables are inserted last
+        clear_tables(&mut transaction).await?;
+        query!(
+            "DELETE FROM data WHERE profile_id != $1",
+            DEFAULT_PROFILE_ID
+        )
+        .execute(&mut *transaction)
+        .await?;
+        query!("DELETE FROM profiles WHERE id != $1", DEFAULT_PROFILE_ID)
+            .execute(&mut *transaction)
+            .await?;
+
+        for profile in &export.profiles {
+            query!(
+                "INSERT INTO profiles (id, name) VALUES ($1, $2)",
+                profile.profile_id,
+                profile.profile_name
+            )
+            .execute(&mut *transaction)
+            .await?;
+            query!(
+                "INSERT INTO data (schema_version, profile_id) VALUES ($1, $2)",
+                SCHEMA_VERSION,
+                profile.profile_id
+            )
+            .execute(&mut *transaction)
+            .await?;
+        }
+
+        for tag in &export.tags {
+            query!(
+                "INSERT INTO tags (id, profile_id, likes, name) VALUES ($1, $2, $3, $4)",
+                tag.id,
+                tag.profile_id,
+                tag.likes,
+                tag.name
+            )
+            .execute(&mut *transaction)
+            .await?;
+        }
+
+        for recipe in &export.recipes {
+            query!(
+                "INSERT INTO recipes (id, name, slug) VALUES ($1, $2, $3)",
+                recipe.id,
+                recipe.name,
+                recipe.slug
+            )
+            .execute(&mut *transaction)
+            .await?;
+        }
+
+        for recipe_tag in &export.recipe_tags {
+            query!(
+                "INSERT INTO recipe_tags (recipe_id, tag_id) VALUES ($1, $2)",
+                recipe_tag.recipe_id,
+                recipe_tag.tag_id
+            )
+            .execute(&mut *transaction)
+            .await?;
+        }
+
+        for previous_recipe in &export.previous_recipes {
+            query!(
+                "INSERT INTO previous_recipes (recipe_id, rated, profile_id, used_at) \
+                VALUES ($1, $2, $3, $4)",
+                previous_recipe.recipe_id,
+                previous_recipe.rated,
+                previous_recipe.profile_id,
+                previous_recipe.used_at
+            )
+            .execute(&mut *transaction)
+            .await?;
+        }
+
+        for pref in &export.recipe_prefs {
+            query!(
+                "INSERT INTO recipe_prefs (recipe_id, profile_id, status) VALUES ($1, $2, $3)",
+                pref.recipe_id,
+                pref.profile_id,
+                pref.status
+            )
+            .execute(&mut *transaction)
+            .await?;
+        }
+
+        for record in &export.rating_history {
+            query!(
+                "INSERT INTO rating_history (recipe_id, tag_id, rating, rated_at, profile_id) \
+                VALUES ($1, $2, $3, $4, $5)",
+                record.recipe_id,
+                record.tag_id,
+                record.rating,
+                record.rated_at,
+                record.profile_id
+            )
+            .execute(&mut *transaction)
+            .await?;
+        }
+
+        for list in &export.shopping_lists {
+            query!(
+                "INSERT INTO shopping_lists (date, content, profile_id) VALUES ($1, $2, $3)",
+                list.date,
+                list.content,
+                list.profile_id
+            )
+            .execute(&mut *transaction)
+            .await?;
+        }
+
+        for used in &export.used_recipes {
+            store_used_recipe(
+                used.recipe_id,
+                used.used_at,
+                used.profile_id,
+                &mut *transaction,
+            )
+            .await?;
+        }
+
+        if export.used_recipes.is_empty() {
+            for statement in BACKFILL_USED_RECIPES {
+                sqlx::query(statement).execute(&mut *transaction).await?;
+            }
+        }
+
+        for data in std::iter::once(&export.data).chain(&export.profiles) {
+            let mode = data.mode.value();
+            query!(
+                "UPDATE data SET mode = $1, offset = $2, last_batch_size = $3, \
+                default_format = $4, default_servings = $5, default_diet = $6 \
+                WHERE profile_id = $7",
+                mode,
+                data.offset,
+                data.last_batch_size,
+                data.settings.default_format,
+                data.settings.default_servings,
+                data.settings.default_diet,
+                data.profile_id
+            )
+            .execute(&mut *transaction)
+            .await?;
+        }
+
+        transaction.commit().await
+    }
+
+    pub async fn store_shopping_list<'c, A>(
+        date: NaiveDate,
+        content: &str,
+        profile_id: i64,
+        conn: A,
+    ) -> Result<(), sqlx::Error>
+    where
+        A: Acquire<'c, Database = Sqlite>,
+    {
+        let mut conn = conn.acquire().await?;
+        let date = date.to_string();
+        query!(
+            "INSERT INTO shopping_lists (date, content, profile_id) VALUES ($1, $2, $3)",
+            date,
+            content,
+            profile_id
+        )
+        .execute(&mut *conn)
+        .await?;
+
+        Ok(())
+    }
+
+    // Oldest first
+    pub async fn get_shopping_list_history(
+        profile_id: i64,
+        pool: &SqlitePool,
+    ) -> Result<Vec<ShoppingListRecord>, sqlx::Error> {
+        query_as!(
+            ShoppingListRecord,
+            "SELECT date, content, profile_id FROM shopping_lists WHERE profile_id = $1 \
+            ORDER BY id",
+            profile_id
+        )
+        .fetch_all(pool)
+        .await
+    }
+
+    pub async fn undo_last_prepare(
+        profile_id: i64,
+        pool: &SqlitePool,
+    ) -> Result<UndoOutcome, sqlx::Error> {
+        if get_mode(profile_id, pool).await? != Mode::Review {
+            return Ok(UndoOutcome::NothingToUndo);
+        }
+
+        // Ratings from a partly finished review have already been applied to the tags
+        let rated = query_scalar!(
+            "SELECT COUNT(*) FROM previous_recipes WHERE rated = 1 AND profile_id = $1",
+            profile_id
+        )
+        .fetch_one(pool)
+        .await?;
+        if rated > 0 {
+            return Ok(UndoOutcome::AlreadyRated);
+        }
+
+        let mut transaction = pool.begin().await?;
+
+        let previous_recipes = query!(
+            "SELECT recipe_id, used_at FROM previous_recipes WHERE profile_id = $1",
+            profile_id
+        )
+        .fetch_all(&mut *transaction)
+        .await?;
+
+        query!(
+            "DELETE FROM previous_recipes WHERE profile_id = $1",
+            profile_id
+        )
+        .execute(&mut *transaction)
+        .await?;
+
+        // Recipes are shared between profiles, so they're only deleted once nobody has used them
+        for previous_recipe in previous_recipes {
+            let recipe_id = previous_recipe.recipe_id;
+            query!(
+                "DELETE FROM used_recipes WHERE recipe_id = $1 AND profile_id = $2 AND used_at = $3",
+                recipe_id,
+                profile_id,
+                previous_recipe.used_at
+            )
+            .execute(&mut *transaction)
+            .await?;
+            query!(
+                "DELETE FROM recipe_tags WHERE recipe_id = $1 \
+                AND NOT EXISTS (SELECT 1 FROM used_recipes WHERE recipe_id = $1)",
+                recipe_id
+            )
+            .execute(&mut *transaction)
+            .await?;
+            query!(
+                "DELETE FROM recipes WHERE id = $1 \
+                AND NOT EXISTS (SELECT 1 FROM used_recipes WHERE recipe_id = $1)",
+                recipe_id
+            )
+            .execute(&mut *transaction)
+            .await?;
+        }
+
+        // Tags the undone run added. Tags that were liked or disliked are kept, since those came
+        // from reviews or preferences.
+        query!(
+            "DELETE FROM tags WHERE profile_id = $1 AND likes = 0 AND id NOT IN ( \
+                SELECT recipe_tags.tag_id FROM recipe_tags \
+                INNER JOIN used_recipes ON used_recipes.recipe_id = recipe_tags.recipe_id \
+                WHERE used_recipes.profile_id = $1 \
+            )",
+            profile_id
+        )
+        .execute(&mut *transaction)
+        .await?;
+
+        let mode = Mode::Prepare.value();
+        query!(
+            "UPDATE data SET offset = MAX(offset - last_batch_size, 0), last_batch_size = 0, mode = $1 \
+            WHERE profile_id = $2",
+            mode,
+            profile_id
+        )
+        .execute(&mut *transaction)
+        .await?;
+
+        transaction.commit().await?;
+
+        Ok(UndoOutcome::Undone)
     }
 
     pub mod models {
-        use std::{fmt::Display, str::FromStr};
+        use super::DEFAULT_PROFILE_ID;
+        use crate::utils::models::{Mode, Rating};
+        use serde::{Deserialize, Serialize};
+        use sqlx::FromRow;
+        #[derive(FromRow, Debug, PartialEq, Eq, Serialize, Deserialize)]
+        pub struct Tag {
+            pub id: i64,
+            // Exports from before profiles existed only have the default profile
+            #[serde(default = "default_profile_id")]
+            pub profile_id: i64,
+            pub likes: i64,
+            pub name: String,
+        }
 
-        #[derive(Debug, Clone, Copy)]
-        pub enum Rating {
-            Dislike = -1,
-            None = 0,
-            Like = 1,
-            Love = 2,
+        fn default_profile_id() -> i64 {
+            DEFAULT_PROFILE_ID
         }
 
-        impl Rating {
-            pub fn value(&self) -> i64 {
-                *self as i64
+        #[derive(FromRow, Debug, PartialEq, Eq, Serialize, Deserialize)]
+        pub struct Recipe {
+            pub id: i64,
+            pub name: String,
+            // Empty for recipes stored before slugs were
+            #[serde(default)]
+            pub slug: String,
+        }
+
+        impl Recipe {
+            pub fn url(&self) -> Option<String> {
+                (!self.slug.is_empty()).then(|| format!("https://tasty.co/recipe/{}", self.slug))
             }
         }
 
-        impl FromStr for Rating {
-            type Err = &'static str;
-            fn from_str(s: &str) -> Result<Self, Self::Err> {
-                use Rating::*;
-                match s.to_lowercase().as_str() {
-                    "dislike" => Ok(Dislike),
-                    "none" => Ok(None),
-                    "like" => Ok(Like),
-                    "love" => Ok(Love),
-                    _ => Err("Please enter dislike, none, like, or love."),
+        #[derive(FromRow, Debug, PartialEq, Eq, Serialize, Deserialize)]
+        pub struct PreviousRecipe {
+            pub recipe_id: i64,
+            #[serde(default)]
+            pub rated: bool,
+            #[serde(default = "default_profile_id")]
+            pub profile_id: i64,
+            // Unix timestamp in seconds, or 0 when it was stored before this was recorded
+            #[serde(default)]
+            pub used_at: i64,
+        }
+
+        #[derive(FromRow, Debug, PartialEq, Eq, Serialize, Deserialize)]
+        pub struct RecipeTag {
+            pub recipe_id: i64,
+            pub tag_id: i64,
+        }
+
+        #[derive(FromRow, Debug, PartialEq, Eq, Serialize, Deserialize)]
+        pub struct RecipePref {
+            pub recipe_id: i64,
+            #[serde(default = "default_profile_id")]
+            pub profile_id: i64,
+            pub status: i64,
+        }
+
+        #[derive(FromRow, Debug, PartialEq, Eq, Serialize, Deserialize)]
+        pub struct RatingRecord {
+            pub recipe_id: i64,
+            pub tag_id: Option<i64>,
+            pub rating: i64,
+            // Unix timestamp in seconds
+            pub rated_at: i64,
+            #[serde(default = "default_profile_id")]
+            pub profile_id: i64,
+        }
+
+        #[derive(FromRow, Debug, PartialEq, Eq, Serialize, Deserialize)]
+        pub struct ShoppingListRecord {
+            // ISO 8601 date the list was made, like 2024-05-01
+            pub date: String,
+            pub content: String,
+            #[serde(default = "default_profile_id")]
+            pub profile_id: i64,
+        }
+
+        #[derive(FromRow, Debug, PartialEq, Eq, Serialize, Deserialize)]
+        pub struct UsedRecipe {
+            pub recipe_id: i64,
+            pub profile_id: i64,
+            // Unix timestamp in seconds, or 0 when it was planned before this was recorded
+            pub used_at: i64,
+        }
+
+        #[derive(Debug, PartialEq, Eq)]
+        pub struct Stats {
+            pub rated_recipes: i64,
+            // Highest rating first, leaving out ratings nobody has given
+            pub rating_counts: Vec<(Rating, i64)>,
+            pub top_tags: Vec<Tag>,
+            pub bottom_tags: Vec<Tag>,
+        }
+
+        // A `data` row without `schema_version`, which always matches the importing database,
+        // along with the name of the profile it belongs to
+        #[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+        pub struct DataExport {
+            pub mode: Mode,
+            pub offset: i64,
+            pub last_batch_size: i64,
+            #[serde(default = "default_profile_id")]
+            pub profile_id: i64,
+            #[serde(default)]
+            pub profile_name: String,
+            #[serde(default, flatten)]
+            pub settings: Settings,
+        }
+
+        // Defaults used when the matching flag isn't given
+        #[derive(FromRow, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+        pub struct Settings {
+            pub default_format: Option<String>,
+            pub default_servings: Option<i64>,
+            pub default_diet: Option<String>,
+        }
+
+        #[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+        pub struct DatabaseExport {
+            pub tags: Vec<Tag>,
+            pub recipes: Vec<Recipe>,
+            pub recipe_tags: Vec<RecipeTag>,
+            pub previous_recipes: Vec<PreviousRecipe>,
+            pub recipe_prefs: Vec<RecipePref>,
+            // Missing from exports made before ratings were recorded
+            #[serde(default)]
+            pub rating_history: Vec<RatingRecord>,
+            #[serde(default)]
+            pub shopping_lists: Vec<ShoppingListRecord>,
+            // Missing from exports made before each profile kept its own history, in which case
+            // it's rebuilt from the other tables
+            #[serde(default)]
+            pub used_recipes: Vec<UsedRecipe>,
+            // The default profile's row
+            pub data: DataExport,
+            // Every other profile's row
+            #[serde(default)]
+            pub profiles: Vec<DataExport>,
+        }
+
+        #[derive(FromRow, Debug, PartialEq, Eq)]
+        pub struct Data {
+            pub mode: Mode,
+            pub offset: i64,
+        }
+    }
+
+    // Shared with the other modules' tests
+    #[cfg(test)]
+    pub mod tests {
+        use super::*;
+        use serde_json::json;
+
+        // Every connection to an in-memory database gets its own, so the pool only has one
+        pub async fn memory_pool() -> SqlitePool {
+            let pool = create_pool("sqlite::memory:", 1).await.unwrap();
+            create_tables(&pool).await.unwrap();
+            populate_data_table(&pool).await.unwrap();
+            run_migrations(&pool).await.unwrap();
+            pool
+        }
+
+        // `tags` are (id, name)
+        pub fn recipe(id: i64, tags: &[(i64, &str)]) -> crate::api::Recipe {
+            let tags: Vec<serde_json::Value> = tags
+                .iter()
+                .map(|(id, name)| json!({ "id": id, "name": name, "display_name": name }))
+                .collect();
+
+            serde_json::from_value(json!({
+                "name": format!("Recipe {}", id),
+                "id": id,
+                "slug": format!("recipe-{}", id),
+                "sections": [{
+                    "name": null,
+                    "components": [{
+                        "ingredient": { "id": id, "display_singular": format!("ingredient {}", id) },
+                        "measurements": [{
+                            "id": 0,
+                            "quantity": "1",
+                            "unit": { "name": "cup", "abbreviation": "c" },
+                        }],
+                    }],
+                }],
+                "tags": tags,
+            }))
+            .unwrap()
+        }
+
+        fn date() -> NaiveDate {
+            NaiveDate::from_ymd_opt(2024, 5, 1).unwrap()
+        }
+
+        async fn save_run(recipes: &[crate::api::Recipe], profile_id: i64, pool: &SqlitePool) {
+            save_prepare_run(recipes, 1, date(), "flour", profile_id, pool)
+                .await
+                .unwrap();
+        }
+
+        #[tokio::test]
+        async fn prepare_runs_store_their_shopping_list() {
+            let pool = memory_pool().await;
+
+            save_run(&[recipe(1, &[])], DEFAULT_PROFILE_ID, &pool).await;
+
+            let history = get_shopping_list_history(DEFAULT_PROFILE_ID, &pool)
+                .await
+                .unwrap();
+            assert_eq!(history.len(), 1);
+            assert_eq!(history[0].date, "2024-05-01");
+            assert_eq!(
+                get_mode(DEFAULT_PROFILE_ID, &pool).await.unwrap(),
+                Mode::Review
+            );
+        }
+
+        #[tokio::test]
+        async fn tags_are_loaded_for_several_recipes_at_once() {
+            let pool = memory_pool().await;
+            let recipes = [
+                recipe(1, &[(10, "easy"), (11, "vegan")]),
+                recipe(2, &[(10, "easy")]),
+                recipe(3, &[]),
+            ];
+            save_run(&recipes, DEFAULT_PROFILE_ID, &pool).await;
+
+            let tags = get_tags_for_recipes(&[1, 2, 3], DEFAULT_PROFILE_ID, &pool)
+                .await
+                .unwrap();
+            let names = |id| -> Vec<String> {
+                let mut names: Vec<String> = tags[&id].iter().map(|t| t.name.clone()).collect();
+                names.sort();
+                names
+            };
+            assert_eq!(names(1), ["easy", "vegan"]);
+            assert_eq!(names(2), ["easy"]);
+            assert!(names(3).is_empty());
+        }
+
+        #[tokio::test]
+        async fn profiles_keep_their_own_used_recipes() {
+            let pool = memory_pool().await;
+            let other = get_or_create_profile("other", &pool).await.unwrap();
+
+            save_run(&[recipe(1, &[(10, "easy")])], DEFAULT_PROFILE_ID, &pool).await;
+
+            assert!(recipe_used(1, DEFAULT_PROFILE_ID, &pool).await.unwrap());
+            assert!(!recipe_used(1, other, &pool).await.unwrap());
+            assert!(search_recipes("Recipe", other, &pool)
+                .await
+                .unwrap()
+                .is_empty());
+        }
+
+        #[tokio::test]
+        async fn undo_keeps_recipes_another_profile_used() {
+            let pool = memory_pool().await;
+            let other = get_or_create_profile("other", &pool).await.unwrap();
+            let shared = [recipe(1, &[(10, "easy")])];
+
+            save_run(&shared, other, &pool).await;
+            save_run(&shared, DEFAULT_PROFILE_ID, &pool).await;
+            assert_eq!(
+                undo_last_prepare(DEFAULT_PROFILE_ID, &pool).await.unwrap(),
+                UndoOutcome::Undone
+            );
+
+            assert!(!recipe_used(1, DEFAULT_PROFILE_ID, &pool).await.unwrap());
+            assert!(recipe_used(1, other, &pool).await.unwrap());
+            assert_eq!(
+                search_recipes("Recipe", other, &pool).await.unwrap().len(),
+                1
+            );
+            assert_eq!(get_recipe_tags(1, other, &pool).await.unwrap().len(), 1);
+        }
+
+        #[tokio::test]
+        async fn undo_deletes_the_tags_the_run_added() {
+            let pool = memory_pool().await;
+            save_run(&[recipe(1, &[(10, "easy")])], DEFAULT_PROFILE_ID, &pool).await;
+            delete_previous_recipes(DEFAULT_PROFILE_ID, &pool)
+                .await
+                .unwrap();
+            set_mode(Mode::Prepare, DEFAULT_PROFILE_ID, &pool)
+                .await
+                .unwrap();
+
+            save_run(
+                &[recipe(2, &[(10, "easy"), (11, "vegan")])],
+                DEFAULT_PROFILE_ID,
+                &pool,
+            )
+            .await;
+            assert_eq!(
+                undo_last_prepare(DEFAULT_PROFILE_ID, &pool).await.unwrap(),
+                UndoOutcome::Undone
+            );
+
+            let tags = get_all_tags(DEFAULT_PROFILE_ID, &pool).await.unwrap();
+            assert_eq!(tags.len(), 1);
+            assert_eq!(tags[0].name, "easy");
+        }
+
+        #[tokio::test]
+        async fn profiles_keep_their_own_pins_and_blacklists() {
+            let pool = memory_pool().await;
+            let other = get_or_create_profile("other", &pool).await.unwrap();
+
+            blacklist_recipe(1, DEFAULT_PROFILE_ID, &pool)
+                .await
+                .unwrap();
+            pin_recipe(1, other, &pool).await.unwrap();
+
+            let blacklisted = |profile_id| {
+                get_recipe_ids_with_status(RecipeStatus::Blacklisted, profile_id, &pool)
+            };
+            assert_eq!(blacklisted(DEFAULT_PROFILE_ID).await.unwrap(), vec![1]);
+            assert!(blacklisted(other).await.unwrap().is_empty());
+            assert_eq!(
+                get_recipe_ids_with_status(RecipeStatus::Pinned, other, &pool)
+                    .await
+                    .unwrap(),
+                vec![1]
+            );
+        }
+
+        #[tokio::test]
+        async fn profiles_keep_their_own_shopping_lists() {
+            let pool = memory_pool().await;
+            let other = get_or_create_profile("other", &pool).await.unwrap();
+            store_shopping_list(date(), "flour", DEFAULT_PROFILE_ID, &pool)
+                .await
+                .unwrap();
+            store_shopping_list(date(), "milk", other, &pool)
+                .await
+                .unwrap();
+
+            let history = get_shopping_list_history(other, &pool).await.unwrap();
+            assert_eq!(history.len(), 1);
+            assert_eq!(history[0].content, "milk");
+        }
+
+        #[tokio::test]
+        async fn version_1_databases_migrate_without_losing_data() {
+            let pool = create_pool("sqlite::memory:", 1).await.unwrap();
+            let review = Mode::Review.value();
+            for statement in [
+                "CREATE TABLE `tags`(`id` INT UNSIGNED NOT NULL PRIMARY KEY, `likes` INT NOT NULL)",
+                "CREATE TABLE `recipes`(`id` INT UNSIGNED NOT NULL PRIMARY KEY, \
+                    `name` VARCHAR(255) NOT NULL)",
+                "CREATE TABLE `previous_recipes`(`recipe_id` INT UNSIGNED NOT NULL, \
+                    FOREIGN KEY(`recipe_id`) REFERENCES recipes(`id`))",
+                "CREATE TABLE `recipe_tags`(`recipe_id` INT UNSIGNED NOT NULL, \
+                    `tag_id` INT UNSIGNED NOT NULL, \
+                    FOREIGN KEY(`recipe_id`) REFERENCES recipes(`id`), \
+                    FOREIGN KEY(`tag_id`) REFERENCES tags(`id`))",
+                "CREATE TABLE `data`(`mode` INT UNSIGNED NOT NULL DEFAULT 0, \
+                    `offset` INT UNSIGNED NOT NULL DEFAULT 0)",
+                "INSERT INTO tags (id, likes) VALUES (5, 3)",
+                "INSERT INTO recipes (id, name) VALUES (1, 'Soup')",
+                "INSERT INTO previous_recipes (recipe_id) VALUES (1)",
+                "INSERT INTO recipe_tags (recipe_id, tag_id) VALUES (1, 5)",
+            ] {
+                sqlx::query(statement).execute(&pool).await.unwrap();
+            }
+            sqlx::query("INSERT INTO data (mode, offset) VALUES ($1, 40)")
+                .bind(review)
+                .execute(&pool)
+                .await
+                .unwrap();
+
+            run_migrations(&pool).await.unwrap();
+
+            assert_eq!(get_schema_version(&pool).await.unwrap(), SCHEMA_VERSION);
+            assert_eq!(
+                get_mode(DEFAULT_PROFILE_ID, &pool).await.unwrap(),
+                Mode::Review
+            );
+            assert_eq!(get_offset(DEFAULT_PROFILE_ID, &pool).await.unwrap(), 40);
+            let tags = get_recipe_tags(1, DEFAULT_PROFILE_ID, &pool).await.unwrap();
+            assert_eq!((tags[0].id, tags[0].likes), (5, 3));
+            assert!(recipe_used(1, DEFAULT_PROFILE_ID, &pool).await.unwrap());
+            assert_eq!(
+                get_or_create_profile("default", &pool).await.unwrap(),
+                DEFAULT_PROFILE_ID
+            );
+        }
+
+        #[tokio::test]
+        async fn partly_reviewed_runs_are_not_undone() {
+            let pool = memory_pool().await;
+            save_run(
+                &[recipe(1, &[(10, "easy")]), recipe(2, &[(10, "easy")])],
+                DEFAULT_PROFILE_ID,
+                &pool,
+            )
+            .await;
+            apply_recipe_rating(1, Rating::Like, &[], DEFAULT_PROFILE_ID, &pool)
+                .await
+                .unwrap();
+
+            assert_eq!(
+                undo_last_prepare(DEFAULT_PROFILE_ID, &pool).await.unwrap(),
+                UndoOutcome::AlreadyRated
+            );
+            assert_eq!(
+                get_mode(DEFAULT_PROFILE_ID, &pool).await.unwrap(),
+                Mode::Review
+            );
+            assert!(recipe_used(2, DEFAULT_PROFILE_ID, &pool).await.unwrap());
+        }
+
+        #[tokio::test]
+        async fn there_is_nothing_to_undo_before_a_prepare_run() {
+            let pool = memory_pool().await;
+
+            assert_eq!(
+                undo_last_prepare(DEFAULT_PROFILE_ID, &pool).await.unwrap(),
+                UndoOutcome::NothingToUndo
+            );
+        }
+
+        #[tokio::test]
+        async fn failed_prepare_runs_change_nothing() {
+            let pool = memory_pool().await;
+            // Storing the shopping list is the run's last write before the offset and mode
+            sqlx::query("DROP TABLE shopping_lists")
+                .execute(&pool)
+                .await
+                .unwrap();
+
+            let result = save_prepare_run(
+                &[recipe(1, &[(10, "easy")])],
+                3,
+                date(),
+                "flour",
+                DEFAULT_PROFILE_ID,
+                &pool,
+            )
+            .await;
+
+            assert!(result.is_err());
+            assert_eq!(get_offset(DEFAULT_PROFILE_ID, &pool).await.unwrap(), 0);
+            assert_eq!(
+                get_mode(DEFAULT_PROFILE_ID, &pool).await.unwrap(),
+                Mode::Prepare
+            );
+            assert!(!recipe_used(1, DEFAULT_PROFILE_ID, &pool).await.unwrap());
+            assert!(get_all_tags(DEFAULT_PROFILE_ID, &pool)
+                .await
+                .unwrap()
+                .is_empty());
+        }
+
+        #[tokio::test]
+        async fn exports_import_into_an_identical_database() {
+            let pool = memory_pool().await;
+            let other = get_or_create_profile("other", &pool).await.unwrap();
+            save_run(&[recipe(1, &[(10, "easy")])], DEFAULT_PROFILE_ID, &pool).await;
+            save_run(&[recipe(2, &[(11, "vegan")])], other, &pool).await;
+            apply_recipe_rating(1, Rating::Like, &[], DEFAULT_PROFILE_ID, &pool)
+                .await
+                .unwrap();
+            pin_recipe(3, other, &pool).await.unwrap();
+            set_default_format(Some("markdown"), other, &pool)
+                .await
+                .unwrap();
+
+            let json = serde_json::to_string(&export_all(&pool).await.unwrap()).unwrap();
+            let restored = memory_pool().await;
+            import_all(&serde_json::from_str(&json).unwrap(), &restored)
+                .await
+                .unwrap();
+
+            assert_eq!(
+                serde_json::to_value(export_all(&restored).await.unwrap()).unwrap(),
+                serde_json::from_str::<serde_json::Value>(&json).unwrap()
+            );
+        }
+
+        #[tokio::test]
+        async fn searches_match_part_of_a_name_in_any_case() {
+            let pool = memory_pool().await;
+            let other = get_or_create_profile("other", &pool).await.unwrap();
+            save_run(
+                &[recipe(1, &[]), recipe(12, &[]), recipe(2, &[])],
+                DEFAULT_PROFILE_ID,
+                &pool,
+            )
+            .await;
+            save_run(&[recipe(13, &[])], other, &pool).await;
+
+            let names = |query: &'static str| {
+                let pool = &pool;
+                async move {
+                    search_recipes(query, DEFAULT_PROFILE_ID, pool)
+                        .await
+                        .unwrap()
+                        .into_iter()
+                        .map(|recipe| recipe.name)
+                        .collect::<Vec<_>>()
                 }
+            };
+
+            assert_eq!(names("recipe 1").await, ["Recipe 1", "Recipe 12"]);
+            assert_eq!(names("PE 2").await, ["Recipe 2"]);
+            assert!(names("pasta").await.is_empty());
+        }
+
+        #[tokio::test]
+        async fn search_wildcards_are_matched_literally() {
+            let pool = memory_pool().await;
+            save_run(&[recipe(1, &[])], DEFAULT_PROFILE_ID, &pool).await;
+
+            for query in ["%", "_", "Recipe_1"] {
+                assert!(search_recipes(query, DEFAULT_PROFILE_ID, &pool)
+                    .await
+                    .unwrap()
+                    .is_empty());
             }
         }
 
-        impl Display for Rating {
-            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-                use Rating::*;
+        #[tokio::test]
+        async fn reviews_resume_with_the_recipes_not_rated_yet() {
+            let pool = memory_pool().await;
+            let recipes = [
+                recipe(1, &[(10, "easy")]),
+                recipe(2, &[(10, "easy")]),
+                recipe(3, &[(11, "vegan")]),
+            ];
+            save_run(&recipes, DEFAULT_PROFILE_ID, &pool).await;
 
-                match self {
-                    Dislike => write!(f, "dislike"),
-                    None => write!(f, "none"),
-                    Like => write!(f, "like"),
-                    Love => write!(f, "love"),
-                }
+            // The first review stops after one recipe
+            apply_recipe_rating(
+                1,
+                Rating::Love,
+                &[(10, Rating::Love)],
+                DEFAULT_PROFILE_ID,
+                &pool,
+            )
+            .await
+            .unwrap();
+
+            let unrated = |pool| async move {
+                let mut ids: Vec<i64> = get_unrated_previous_recipes(DEFAULT_PROFILE_ID, pool)
+                    .await
+                    .unwrap()
+                    .iter()
+                    .map(|recipe| recipe.id)
+                    .collect();
+                ids.sort();
+                ids
+            };
+            assert_eq!(unrated(&pool).await, [2, 3]);
+            assert_eq!(
+                get_mode(DEFAULT_PROFILE_ID, &pool).await.unwrap(),
+                Mode::Review
+            );
+
+            // Running the review again only asks about the rest
+            for id in unrated(&pool).await {
+                let tag_id = if id == 3 { 11 } else { 10 };
+                apply_recipe_rating(
+                    id,
+                    Rating::Like,
+                    &[(tag_id, Rating::Like)],
+                    DEFAULT_PROFILE_ID,
+                    &pool,
+                )
+                .await
+                .unwrap();
             }
+            assert!(unrated(&pool).await.is_empty());
+
+            let likes: HashMap<String, i64> = get_all_tags(DEFAULT_PROFILE_ID, &pool)
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|tag| (tag.name, tag.likes))
+                .collect();
+            assert_eq!(likes["easy"], 3);
+            assert_eq!(likes["vegan"], 1);
         }
 
-        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-        pub enum Mode {
-            Prepare = 0,
-            Review = 1,
+        #[tokio::test]
+        async fn old_ratings_count_for_less_with_a_half_life() {
+            let pool = memory_pool().await;
+            save_run(
+                &[recipe(1, &[(10, "easy")]), recipe(2, &[(11, "vegan")])],
+                DEFAULT_PROFILE_ID,
+                &pool,
+            )
+            .await;
+            apply_recipe_rating(
+                1,
+                Rating::Love,
+                &[(10, Rating::Love)],
+                DEFAULT_PROFILE_ID,
+                &pool,
+            )
+            .await
+            .unwrap();
+            apply_recipe_rating(
+                2,
+                Rating::Like,
+                &[(11, Rating::Like)],
+                DEFAULT_PROFILE_ID,
+                &pool,
+            )
+            .await
+            .unwrap();
+
+            // The first recipe was rated four weeks ago
+            sqlx::query("UPDATE rating_history SET rated_at = rated_at - $1 WHERE recipe_id = 1")
+                .bind(4 * 7 * 24 * 60 * 60)
+                .execute(&pool)
+                .await
+                .unwrap();
+
+            let likes_with = |half_life| {
+                let pool = &pool;
+                async move {
+                    recompute_tag_likes(half_life, DEFAULT_PROFILE_ID, pool)
+                        .await
+                        .unwrap();
+                    let likes: HashMap<String, i64> = get_all_tags(DEFAULT_PROFILE_ID, pool)
+                        .await
+                        .unwrap()
+                        .into_iter()
+                        .map(|tag| (tag.name, tag.likes))
+                        .collect();
+                    (likes["easy"], likes["vegan"])
+                }
+            };
+
+            assert_eq!(likes_with(None).await, (2, 1));
+            assert_eq!(likes_with(Some(0.0)).await, (2, 1));
+            // One half-life old, so the love counts half
+            assert_eq!(likes_with(Some(4.0)).await, (1, 1));
+            // Four half-lives old, so it rounds away
+            assert_eq!(likes_with(Some(1.0)).await, (0, 1));
         }
 
-        impl Mode {
-            pub fn value(&self) -> i64 {
-                *self as i64
+        #[tokio::test]
+        async fn resets_leave_a_fresh_database() {
+            let pool = memory_pool().await;
+            let other = get_or_create_profile("other", &pool).await.unwrap();
+            save_run(&[recipe(1, &[(10, "easy")])], DEFAULT_PROFILE_ID, &pool).await;
+            save_run(&[recipe(2, &[(11, "vegan")])], other, &pool).await;
+            apply_recipe_rating(1, Rating::Like, &[], DEFAULT_PROFILE_ID, &pool)
+                .await
+                .unwrap();
+            pin_recipe(3, DEFAULT_PROFILE_ID, &pool).await.unwrap();
+
+            reset_all(&pool).await.unwrap();
+
+            for table in [
+                "tags",
+                "recipes",
+                "recipe_tags",
+                "previous_recipes",
+                "used_recipes",
+                "recipe_prefs",
+                "rating_history",
+                "shopping_lists",
+            ] {
+                let count: i64 = sqlx::query_scalar(&format!("SELECT COUNT(*) FROM {}", table))
+                    .fetch_one(&pool)
+                    .await
+                    .unwrap();
+                assert_eq!(count, 0, "{} still has rows", table);
             }
+            // Only the default profile is left
+            assert_eq!(find_profile("other", &pool).await.unwrap(), None);
+            assert_eq!(
+                serde_json::to_value(export_all(&pool).await.unwrap()).unwrap(),
+                serde_json::to_value(export_all(&memory_pool().await).await.unwrap()).unwrap()
+            );
         }
 
-        impl Into<Mode> for i64 {
-            fn into(self) -> Mode {
-                match self {
-                    0 => Mode::Prepare,
-                    1 => Mode::Review,
-                    _ => panic!("`data` table contains a `mode` value other than 0 or 1"),
-                }
+        #[tokio::test]
+        async fn batched_tags_match_each_recipes_own_tags() {
+            let pool = memory_pool().await;
+            let other = get_or_create_profile("other", &pool).await.unwrap();
+            save_run(
+                &[
+                    recipe(1, &[(10, "easy"), (11, "vegan")]),
+                    recipe(2, &[(11, "vegan"), (12, "quick")]),
+                    recipe(3, &[]),
+                ],
+                DEFAULT_PROFILE_ID,
+                &pool,
+            )
+            .await;
+            save_run(&[recipe(4, &[(10, "easy")])], other, &pool).await;
+            set_tag_likes(11, 3, DEFAULT_PROFILE_ID, &pool)
+                .await
+                .unwrap();
+
+            let ids = [1, 2, 3, 4, 5];
+            let mut batched = get_tags_for_recipes(&ids, DEFAULT_PROFILE_ID, &pool)
+                .await
+                .unwrap();
+
+            let sorted = |mut tags: Vec<Tag>| {
+                tags.sort_by_key(|tag| tag.id);
+                tags
+            };
+            for id in ids {
+                let own = get_recipe_tags(id, DEFAULT_PROFILE_ID, &pool)
+                    .await
+                    .unwrap();
+                assert_eq!(
+                    sorted(batched.remove(&id).unwrap()),
+                    sorted(own),
+                    "recipe {}",
+                    id
+                );
             }
         }
     }
 }
 
-pub mod database {
-    use crate::utils::models::Mode;
-    use futures::future::join_all;
-    pub use models::Recipe;
-    use models::{Data, RecipeTag, Tag};
-    use sqlx::{query, query_as, SqlitePool};
-
-    pub async fn tables_exist(pool: &SqlitePool) -> bool {
-        query!("SELECT * FROM data LIMIT 1")
-            .fetch_optional(pool)
-            .await
-            .unwrap_or(None)
-            .is_some()
-    }
+pub use plan::{
+    build_plan, generate_plan, generate_stateless_plan, rank_candidates, Plan, PlanOptions,
+};
 
-    pub async fn create_tables(pool: &SqlitePool) -> Result<(), sqlx::Error> {
-        query!(
-            "CREATE TABLE IF NOT EXISTS `tags`( \
-                `id`    INT UNSIGNED NOT NULL PRIMARY KEY, \
-                `likes` INT NOT NULL \
-            )"
-        )
-        .execute(pool)
-        .await?;
-        query!(
-            "CREATE TABLE IF NOT EXISTS `recipes`( \
-                `id`   INT UNSIGNED NOT NULL PRIMARY KEY, \
-                `name` VARCHAR(255) NOT NULL \
-            )"
-        )
-        .execute(pool)
-        .await?;
-        query!(
-            "CREATE TABLE IF NOT EXISTS `previous_recipes`( \
-                `recipe_id`              INT UNSIGNED NOT NULL, \
-                FOREIGN KEY(`recipe_id`) REFERENCES recipes(`id`) \
-            )"
-        )
-        .execute(pool)
-        .await?;
-        query!(
-            "CREATE TABLE IF NOT EXISTS `recipe_tags`( \
-                `recipe_id`              INT UNSIGNED NOT NULL, \
-                `tag_id`                 INT UNSIGNED NOT NULL, \
-                FOREIGN KEY(`recipe_id`) REFERENCES recipes(`id`), \
-                FOREIGN KEY(`tag_id`)    REFERENCES tags(`id`) \
-            )"
-        )
-        .execute(pool)
-        .await?;
-        query!(
-            "CREATE TABLE IF NOT EXISTS `data`( \
-                `mode`   INT UNSIGNED NOT NULL DEFAULT 0, \
-                `offset` INT UNSIGNED NOT NULL DEFAULT 0 \
-            )"
-        )
-        .execute(pool)
-        .await?;
+// The one error type returned by the library's top-level functions
+#[derive(thiserror::Error, Debug)]
+pub enum MealPlannerError {
+    #[error(transparent)]
+    Sql(#[from] sqlx::Error),
+    #[error(transparent)]
+    Api(#[from] api::models::ApiError),
+    #[error("no recipes were available")]
+    NoRecipes,
+}
 
-        Ok(())
+impl From<reqwest::Error> for MealPlannerError {
+    fn from(e: reqwest::Error) -> Self {
+        MealPlannerError::Api(e.into())
     }
+}
 
-    pub async fn populate_data_table(pool: &SqlitePool) -> Result<(), sqlx::Error> {
-        query!("INSERT INTO data DEFAULT VALUES")
-            .execute(pool)
-            .await?;
-
-        Ok(())
-    }
+pub mod plan {
+    use crate::api::{
+        build_shopping_list, clamp_page_size, get_components, models::ShoppingList,
+        scale_component, Recipe, RecipeSource, MAX_PAGE_SIZE,
+    };
+    use crate::database::{get_offset, get_recipe_ids_with_status, DEFAULT_PROFILE_ID};
+    use crate::utils::{
+        dedup_recipes_by_id, get_matching_recipes_filtered,
+        models::{RecipeFilter, RecipeStatus, Strategy, UnitSystem},
+        pantry_ingredient_ids, remove_duplicate_recipes, select_recipes_stateless,
+        synonyms::canonical_ingredient,
+    };
+    use crate::MealPlannerError;
+    use log::{debug, warn};
+    use sqlx::SqlitePool;
 
-    pub async fn get_mode(pool: &SqlitePool) -> Result<Mode, sqlx::Error> {
-        let data = query_as!(Data, "SELECT mode, offset FROM data LIMIT 1")
-            .fetch_one(pool)
-            .await?;
+    const DEFAULT_MAX_PAGES: u32 = 5;
 
-        Ok(data.mode)
+    #[derive(Debug, Clone)]
+    pub struct PlanOptions {
+        // Servings each recipe is scaled to, or `None` to use the recipes as written
+        pub servings: Option<i64>,
+        pub filter: RecipeFilter,
+        pub max_pages: u32,
+        // Recipes requested per page, clamped to what the API allows
+        pub page_size: i64,
+        // Ingredient names or ids left off the shopping list
+        pub pantry: Vec<String>,
+        pub strategy: Strategy,
+        // Whether ingredients like "scallion" and "green onion" are combined
+        pub merge_synonyms: bool,
+        // Makes random selection repeatable
+        pub seed: Option<u64>,
+        pub unit_system: UnitSystem,
+        // Tolerance for rounding to practical kitchen amounts, or `None` to keep amounts exact
+        pub rounding: Option<f64>,
+        // Whose likes and offset are used
+        pub profile_id: i64,
     }
 
-    pub async fn get_offset(pool: &SqlitePool) -> Result<i64, sqlx::Error> {
-        let data = query_as!(Data, "SELECT mode, offset FROM data LIMIT 1")
-            .fetch_one(pool)
-            .await?;
+    impl Default for PlanOptions {
+        fn default() -> Self {
+            PlanOptions {
+                servings: None,
+                filter: RecipeFilter::default(),
+                max_pages: DEFAULT_MAX_PAGES,
+                page_size: MAX_PAGE_SIZE,
+                pantry: Vec::new(),
+                strategy: Strategy::default(),
+                merge_synonyms: false,
+                seed: None,
+                unit_system: UnitSystem::default(),
+                rounding: None,
+                profile_id: DEFAULT_PROFILE_ID,
+            }
+        }
+    }
 
-        Ok(data.offset)
+    #[derive(Debug)]
+    pub struct Plan {
+        pub recipes: Vec<Recipe>,
+        pub shopping_list: ShoppingList,
+        // How far the stored offset should advance once the plan is accepted
+        pub offset_consumed: i64,
     }
 
-    pub async fn get_previous_recipes(pool: &SqlitePool) -> Result<Vec<Recipe>, sqlx::Error> {
-        query_as!(Recipe, "SELECT recipes.id, recipes.name FROM recipes INNER JOIN previous_recipes ON recipes.id = previous_recipes.recipe_id")
-            .fetch_all(pool)
-            .await
+    pub struct Candidates {
+        pub recipes: Vec<Recipe>,
+        pub pages_fetched: u32,
     }
 
-    pub async fn get_recipe_tags(
-        recipe_id: i64,
+    // Fetches pages until there are `needed` new recipes passing `filter`, the catalog runs out,
+    // or `max_pages` pages have been fetched
+    pub async fn collect_candidate_recipes(
         pool: &SqlitePool,
-    ) -> Result<Vec<Tag>, sqlx::Error> {
-        let tag_ids: Vec<i64> = query_as!(
-            RecipeTag,
-            "SELECT recipe_id, tag_id FROM recipe_tags WHERE recipe_id = $1",
-            recipe_id
+        source: &impl RecipeSource,
+        needed: i64,
+        max_pages: u32,
+        page_size: i64,
+        filter: &RecipeFilter,
+        profile_id: i64,
+    ) -> Result<Candidates, MealPlannerError> {
+        let offset = get_offset(profile_id, pool).await?;
+
+        fetch_candidates(
+            Some((pool, profile_id)),
+            source,
+            offset,
+            needed,
+            max_pages,
+            page_size,
+            filter,
         )
-        .fetch_all(pool)
-        .await?
-        .into_iter()
-        .map(|rt| rt.tag_id)
-        .collect();
+        .await
+    }
 
-        let tag_futures = tag_ids
-            .into_iter()
-            .map(|tag_id| get_tag_by_id(tag_id, pool));
+    // Skips the recipes the profile in `history` has already planned, going by the filter's
+    // cooldown. Without a pool, recipes suggested on earlier runs can't be skipped.
+    async fn fetch_candidates(
+        history: Option<(&SqlitePool, i64)>,
+        source: &impl RecipeSource,
+        offset: i64,
+        needed: i64,
+        max_pages: u32,
+        page_size: i64,
+        filter: &RecipeFilter,
+    ) -> Result<Candidates, MealPlannerError> {
+        let page_size = clamp_page_size(page_size);
+        let mut recipes: Vec<Recipe> = Vec::new();
+        let mut pages_fetched: u32 = 0;
 
-        join_all(tag_futures).await.into_iter().collect()
-    }
+        while pages_fetched < max_pages.max(1) {
+            let page = source
+                .list(offset + pages_fetched as i64 * page_size, page_size)
+                .await?;
+            pages_fetched += 1;
+            debug!("Fetched page {} with {} recipes", pages_fetched, page.len());
 
-    async fn get_tag_by_id(id: i64, pool: &SqlitePool) -> Result<Tag, sqlx::Error> {
-        query_as!(Tag, "SELECT id, likes FROM tags WHERE id = $1 LIMIT 1", id)
-            .fetch_one(pool)
-            .await
-    }
+            if page.is_empty() {
+                break;
+            }
 
-    pub async fn update_tag_likes(
-        id: i64,
-        value: i64,
-        pool: &SqlitePool,
-    ) -> Result<(), sqlx::Error> {
-        query!(
-            "UPDATE tags SET likes = likes + $1 WHERE id = $2",
-            value,
-            id
-        )
-        .execute(pool)
-        .await?;
+            let page = match history {
+                Some((pool, profile_id)) => {
+                    remove_duplicate_recipes(page, filter.cooldown_days, profile_id, pool).await?
+                }
+                None => page,
+            };
 
-        Ok(())
-    }
+            for recipe in page {
+                if filter.matches(&recipe) && recipes.iter().all(|r| r.id != recipe.id) {
+                    recipes.push(recipe);
+                }
+            }
 
-    pub async fn delete_previous_recipes(pool: &SqlitePool) -> Result<(), sqlx::Error> {
-        query!("DELETE FROM previous_recipes").execute(pool).await?;
+            if recipes.len() as i64 >= needed {
+                break;
+            }
+        }
 
-        Ok(())
+        Ok(Candidates {
+            recipes,
+            pages_fetched,
+        })
     }
 
-    pub async fn set_mode(mode: Mode, pool: &SqlitePool) -> Result<(), sqlx::Error> {
-        let value = mode.value();
-        query!("UPDATE data SET mode = $1", value)
-            .execute(pool)
-            .await?;
+    pub async fn generate_plan(
+        pool: &SqlitePool,
+        source: &impl RecipeSource,
+        n_recipes: i64,
+        options: PlanOptions,
+    ) -> Result<Plan, MealPlannerError> {
+        let mut candidates = rank_candidates(Some(pool), source, n_recipes, &options).await?;
+        candidates.recipes.truncate(n_recipes.max(0) as usize);
 
-        Ok(())
+        build_plan(
+            candidates.recipes,
+            candidates.pages_fetched,
+            n_recipes,
+            &options,
+        )
     }
 
-    pub async fn recipe_exists(recipe_id: i64, pool: &SqlitePool) -> Result<bool, sqlx::Error> {
-        Ok(
-            query!("SELECT * FROM recipes WHERE id = $1 LIMIT 1", recipe_id)
-                .fetch_optional(pool)
-                .await?
-                .is_some(),
+    // Plans from the start of the catalog without reading or writing a database
+    pub async fn generate_stateless_plan(
+        source: &impl RecipeSource,
+        n_recipes: i64,
+        options: PlanOptions,
+    ) -> Result<Plan, MealPlannerError> {
+        let mut candidates = rank_candidates(None, source, n_recipes, &options).await?;
+        candidates.recipes.truncate(n_recipes.max(0) as usize);
+
+        build_plan(
+            candidates.recipes,
+            candidates.pages_fetched,
+            n_recipes,
+            &options,
         )
     }
 
-    pub async fn store_tag(tag_id: i64, pool: &SqlitePool) -> Result<(), sqlx::Error> {
-        query!(
-            "INSERT OR IGNORE INTO tags (id, likes) VALUES ($1, 0)",
-            tag_id
+    // Fetches at least `needed` candidates when the catalog has them and returns every one of
+    // them best first. Without a pool nothing is scored and the stored offset is ignored.
+    pub async fn rank_candidates(
+        pool: Option<&SqlitePool>,
+        source: &impl RecipeSource,
+        needed: i64,
+        options: &PlanOptions,
+    ) -> Result<Candidates, MealPlannerError> {
+        let offset = match pool {
+            Some(pool) => get_offset(options.profile_id, pool).await?,
+            None => 0,
+        };
+        let mut candidates = fetch_candidates(
+            pool.map(|pool| (pool, options.profile_id)),
+            source,
+            offset,
+            needed,
+            options.max_pages,
+            options.page_size,
+            &options.filter,
         )
-        .execute(pool)
         .await?;
 
-        Ok(())
-    }
-    pub async fn store_recipe_tag_relationship(
-        recipe_id: i64,
-        tag_id: i64,
-        pool: &SqlitePool,
-    ) -> Result<(), sqlx::Error> {
-        store_tag(tag_id, pool).await?;
+        if let Some(pool) = pool {
+            candidates.recipes.extend(
+                fetch_missing_pins(
+                    pool,
+                    source,
+                    &candidates.recipes,
+                    &options.filter,
+                    options.profile_id,
+                )
+                .await?,
+            );
+        }
+        let count = candidates.recipes.len() as i64;
 
-        query!(
-            "INSERT INTO recipe_tags (recipe_id, tag_id) VALUES ($1, $2)",
-            recipe_id,
-            tag_id
-        )
-        .execute(pool)
-        .await?;
+        let ranked = match pool {
+            Some(pool) => {
+                get_matching_recipes_filtered(
+                    candidates.recipes,
+                    count,
+                    &options.filter,
+                    options.strategy,
+                    options.seed,
+                    options.profile_id,
+                    pool,
+                )
+                .await?
+            }
+            None => select_recipes_stateless(
+                candidates.recipes,
+                count,
+                &options.filter,
+                options.strategy,
+                options.seed,
+            ),
+        };
 
-        Ok(())
+        Ok(Candidates {
+            recipes: dedup_recipes_by_id(ranked),
+            pages_fetched: candidates.pages_fetched,
+        })
     }
 
-    pub async fn store_recipe(
-        recipe: &crate::api::Recipe,
+    // Pinned recipes that weren't on the fetched pages, looked up by id
+    async fn fetch_missing_pins(
         pool: &SqlitePool,
-    ) -> Result<(), sqlx::Error> {
-        query!(
-            "INSERT OR IGNORE INTO recipes (id, name) VALUES ($1, $2)",
-            recipe.id,
-            recipe.name,
-        )
-        .execute(pool)
-        .await?;
+        source: &impl RecipeSource,
+        fetched: &[Recipe],
+        filter: &RecipeFilter,
+        profile_id: i64,
+    ) -> Result<Vec<Recipe>, MealPlannerError> {
+        let mut pins: Vec<Recipe> = Vec::new();
 
-        for tag in &recipe.tags {
-            store_recipe_tag_relationship(recipe.id, tag.id, pool).await?;
+        for id in get_recipe_ids_with_status(RecipeStatus::Pinned, profile_id, pool).await? {
+            if fetched.iter().any(|recipe| recipe.id == id) {
+                continue;
+            }
+
+            match source.get(id).await? {
+                Some(recipe) if filter.matches(&recipe) => pins.push(recipe),
+                Some(_) => debug!("Pinned recipe {} doesn't pass the filters", id),
+                None => warn!("Pinned recipe {} wasn't found", id),
+            }
         }
 
-        Ok(())
+        Ok(pins)
     }
 
-    pub async fn store_previous_recipe(
-        recipe: &crate::api::Recipe,
-        pool: &SqlitePool,
-    ) -> Result<(), sqlx::Error> {
-        query!(
-            "INSERT INTO previous_recipes (recipe_id) VALUES ($1)",
-            recipe.id
-        )
-        .execute(pool)
-        .await?;
+    // Scales and combines the chosen recipes; `n_recipes` is how many were asked for, which
+    // decides how far the offset moves
+    pub fn build_plan(
+        recipes: Vec<Recipe>,
+        pages_fetched: u32,
+        n_recipes: i64,
+        options: &PlanOptions,
+    ) -> Result<Plan, MealPlannerError> {
+        // An empty plan would still move the offset and leave nothing to review
+        if recipes.is_empty() {
+            return Err(MealPlannerError::NoRecipes);
+        }
 
-        Ok(())
-    }
+        let mut components = Vec::new();
 
-    pub async fn increment_offset(n: i64, pool: &SqlitePool) -> Result<(), sqlx::Error> {
-        query!("UPDATE data SET offset = offset+$1", n)
-            .execute(pool)
-            .await?;
+        for recipe in &recipes {
+            let factor = match (options.servings, recipe.num_servings) {
+                (Some(servings), Some(n)) if n > 0 => servings as f64 / n as f64,
+                _ => 1.0,
+            };
 
-        Ok(())
+            for mut component in get_components(std::slice::from_ref(recipe)) {
+                if options.merge_synonyms {
+                    component.ingredient = canonical_ingredient(&component.ingredient);
+                }
+
+                components.push(scale_component(&component, factor));
+            }
+        }
+
+        let excluded = pantry_ingredient_ids(&options.pantry, &components);
+        let shopping_list =
+            build_shopping_list(components, &excluded, options.unit_system, options.rounding);
+
+        // Every page before the last one was used up, so skip past them entirely
+        let offset_consumed =
+            (pages_fetched as i64 - 1) * clamp_page_size(options.page_size) + n_recipes;
+
+        Ok(Plan {
+            recipes,
+            shopping_list,
+            offset_consumed,
+        })
     }
 
-    pub mod models {
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::api::MockSource;
+        use crate::database::{
+            create_pool, create_tables, get_mode, get_offset, open_existing, pin_recipe,
+            populate_data_table, run_migrations, set_offset, store_used_recipe,
+            tests::{memory_pool, recipe},
+        };
         use crate::utils::models::Mode;
-        use serde::Deserialize;
-        use sqlx::FromRow;
-        #[derive(FromRow, Debug, PartialEq, Eq)]
-        pub struct Tag {
-            pub id: i64,
-            pub likes: i64,
+
+        fn source(ids: std::ops::RangeInclusive<i64>) -> MockSource {
+            MockSource {
+                recipes: ids.map(|id| recipe(id, &[])).collect(),
+            }
         }
 
-        #[derive(FromRow, Debug, PartialEq, Eq, Deserialize)]
-        pub struct Recipe {
-            pub id: i64,
-            pub name: String,
+        fn ids(recipes: &[Recipe]) -> Vec<i64> {
+            recipes.iter().map(|recipe| recipe.id).collect()
         }
 
-        #[derive(FromRow, Debug, PartialEq, Eq)]
-        pub struct PreviousRecipe {
-            pub recipe_id: i64,
+        #[tokio::test]
+        async fn pinned_recipes_off_the_fetched_pages_are_fetched_by_id() {
+            let pool = memory_pool().await;
+            pin_recipe(5, DEFAULT_PROFILE_ID, &pool).await.unwrap();
+            let options = PlanOptions {
+                max_pages: 1,
+                page_size: 2,
+                ..PlanOptions::default()
+            };
+
+            let candidates = rank_candidates(Some(&pool), &source(1..=5), 2, &options)
+                .await
+                .unwrap();
+
+            assert_eq!(ids(&candidates.recipes)[0], 5);
+            assert_eq!(candidates.recipes.len(), 3);
         }
 
-        #[derive(FromRow, Debug, PartialEq, Eq)]
-        pub struct RecipeTag {
-            pub recipe_id: i64,
-            pub tag_id: i64,
+        #[tokio::test]
+        async fn pinned_recipes_are_picked_again_after_being_used() {
+            let pool = memory_pool().await;
+            pin_recipe(2, DEFAULT_PROFILE_ID, &pool).await.unwrap();
+            for id in [1, 2] {
+                store_used_recipe(id, 0, DEFAULT_PROFILE_ID, &pool)
+                    .await
+                    .unwrap();
+            }
+
+            let candidates =
+                rank_candidates(Some(&pool), &source(1..=3), 2, &PlanOptions::default())
+                    .await
+                    .unwrap();
+
+            assert_eq!(ids(&candidates.recipes), vec![2, 3]);
         }
 
-        #[derive(FromRow, Debug, PartialEq, Eq)]
-        pub struct Data {
-            pub mode: Mode,
-            pub offset: i64,
+        #[tokio::test]
+        async fn plans_skip_used_recipes_and_advance_the_offset() {
+            let pool = memory_pool().await;
+            store_used_recipe(1, 0, DEFAULT_PROFILE_ID, &pool)
+                .await
+                .unwrap();
+            let options = PlanOptions {
+                max_pages: 1,
+                page_size: 3,
+                ..PlanOptions::default()
+            };
+
+            let plan = generate_plan(&pool, &source(1..=6), 2, options)
+                .await
+                .unwrap();
+
+            let mut planned = ids(&plan.recipes);
+            planned.sort();
+            assert_eq!(planned, vec![2, 3]);
+            assert_eq!(plan.shopping_list.items.len(), 2);
+            assert_eq!(plan.offset_consumed, 2);
+        }
+
+        #[tokio::test]
+        async fn stateless_plans_start_at_the_beginning_of_the_catalog() {
+            let plan = generate_stateless_plan(&source(1..=6), 3, PlanOptions::default())
+                .await
+                .unwrap();
+
+            assert_eq!(ids(&plan.recipes), vec![1, 2, 3]);
+        }
+
+        #[tokio::test]
+        async fn an_empty_catalog_makes_no_plan() {
+            let empty = MockSource {
+                recipes: Vec::new(),
+            };
+            let result = generate_stateless_plan(&empty, 3, PlanOptions::default()).await;
+
+            assert!(matches!(result, Err(MealPlannerError::NoRecipes)));
+        }
+
+        #[test]
+        fn errors_say_what_went_wrong() {
+            let error = MealPlannerError::from(crate::api::models::ApiError::Timeout(
+                std::time::Duration::from_secs(5),
+            ));
+
+            assert_eq!(error.to_string(), "request timed out after 5s");
+            assert_eq!(
+                MealPlannerError::from(sqlx::Error::RowNotFound).to_string(),
+                sqlx::Error::RowNotFound.to_string()
+            );
+        }
+
+        #[tokio::test]
+        async fn pages_are_fetched_until_there_are_enough_new_recipes() {
+            let pool = memory_pool().await;
+            for id in [1, 2] {
+                store_used_recipe(id, 0, DEFAULT_PROFILE_ID, &pool)
+                    .await
+                    .unwrap();
+            }
+            let options = PlanOptions {
+                page_size: 2,
+                ..PlanOptions::default()
+            };
+
+            let candidates = rank_candidates(Some(&pool), &source(1..=5), 3, &options)
+                .await
+                .unwrap();
+
+            let mut found = ids(&candidates.recipes);
+            found.sort();
+            assert_eq!(found, vec![3, 4, 5]);
+            assert_eq!(candidates.pages_fetched, 3);
+        }
+
+        #[tokio::test]
+        async fn dry_runs_leave_the_offset_and_mode_alone() {
+            let path = std::env::temp_dir()
+                .join(format!("meal-planner-dry-run-{}.db", std::process::id()));
+            let writable = create_pool(&format!("sqlite://{}?mode=rwc", path.display()), 1)
+                .await
+                .unwrap();
+            create_tables(&writable).await.unwrap();
+            populate_data_table(&writable).await.unwrap();
+            run_migrations(&writable).await.unwrap();
+            set_offset(4, DEFAULT_PROFILE_ID, &writable).await.unwrap();
+            writable.close().await;
+
+            let pool = open_existing(&path, 1).await.unwrap().unwrap();
+            let plan = generate_plan(&pool, &source(1..=10), 2, PlanOptions::default())
+                .await
+                .unwrap();
+
+            assert_eq!(ids(&plan.recipes), vec![5, 6]);
+            assert!(set_offset(0, DEFAULT_PROFILE_ID, &pool).await.is_err());
+            assert_eq!(get_offset(DEFAULT_PROFILE_ID, &pool).await.unwrap(), 4);
+            assert_eq!(
+                get_mode(DEFAULT_PROFILE_ID, &pool).await.unwrap(),
+                Mode::Prepare
+            );
+
+            pool.close().await;
+            std::fs::remove_file(&path).unwrap();
+        }
+
+        #[tokio::test]
+        async fn missing_databases_are_not_created_for_dry_runs() {
+            let path = std::env::temp_dir().join("meal-planner-missing.db");
+
+            assert!(open_existing(&path, 1).await.unwrap().is_none());
+            assert!(!path.exists());
         }
     }
 }
@@ -2,154 +2,998 @@
 
 use meal_planner::{
     api::{
-        get_components, get_recipes_list, make_shopping_list, models::IncompatibleComponentError,
+        get_components,
+        models::{ApiError, Recipe},
+        recipe_ingredient_breakdown, recipe_instructions, recipe_line, recipes_to_markdown,
+        render_html, shopping_list_to_csv, shopping_list_to_json, shopping_list_to_markdown,
+        shopping_list_to_tasks, TastyApi, DEFAULT_ROUNDING_TOLERANCE, MAX_PAGE_SIZE,
     },
+    build_plan,
     database::{
-        self, create_tables, delete_previous_recipes, get_mode, get_offset, get_previous_recipes,
-        get_recipe_tags, increment_offset, populate_data_table, set_mode, store_previous_recipe,
-        store_recipe, tables_exist, update_tag_likes,
+        self, apply_recipe_rating, blacklist_recipe, create_pool, create_tables,
+        delete_previous_recipes, export_all, find_profile, get_all_tags, get_mode,
+        get_or_create_profile, get_settings, get_shopping_list_history, get_tags_for_recipes,
+        get_unrated_previous_recipes, import_all, is_locked_error,
+        models::{DatabaseExport, Stats},
+        open_existing, pin_recipe, populate_data_table, rating_stats, recipes_with_tag,
+        recompute_tag_likes, reset_all, run_migrations, save_prepare_run, search_recipes,
+        set_default_diet, set_default_format, set_default_servings, set_mode, set_offset,
+        set_tag_likes, tables_exist, undo_last_prepare, DEFAULT_MAX_CONNECTIONS,
+        DEFAULT_PROFILE_ID,
     },
+    generate_plan, generate_stateless_plan, rank_candidates,
     utils::{
-        get_matching_recipes,
-        models::{Mode, Rating},
-        open_file, remove_duplicate_recipes, validation_input,
+        choose_recipes,
+        config::{config_path, load_config, resolve_api_key_from, Config, KeyError},
+        diets::{diet_tag_ids, Diet},
+        format_plan_timestamp,
+        models::{
+            InputError, Mode, OutputFormat, QuantityStyle, Rating, RecipeFilter, Strategy,
+            UndoOutcome, UnitSystem,
+        },
+        most_recent_outputs, open_file, pantry_ingredient_ids,
+        pricing::{estimate_cost, load_prices},
+        resolve_db_path,
+        substitutions::suggest_substitutions,
+        validation_input, DEFAULT_TIME_FORMAT,
     },
+    MealPlannerError, Plan, PlanOptions,
 };
 use spinoff::{spinners, Color, Spinner};
-use sqlx::{self, sqlite::SqlitePoolOptions, SqlitePool};
+use sqlx::{self, SqlitePool};
 
 use tokio::{fs::OpenOptions, io::AsyncWriteExt};
 
-use chrono::Local;
+use chrono::{FixedOffset, Local};
 
 use std::{
+    collections::HashMap,
     env,
+    fmt::Display,
     io::{self, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
 };
 use thiserror::Error;
 
 #[derive(Error, Debug)]
+#[allow(clippy::enum_variant_names)]
 enum PrepareError {
     #[error("sql error")]
-    SqlError(#[from] sqlx::Error),
-    #[error("environment variable error")]
-    EnvError(#[from] env::VarError),
-    #[error("reqwests error")]
-    ReqError(#[from] reqwest::Error),
-    #[error("incompatible component error")]
-    CmpError(#[from] IncompatibleComponentError),
+    SqlError(#[source] sqlx::Error),
+    #[error("database is in use by another meal_planner process")]
+    DatabaseLockedError,
+    #[error("missing api key")]
+    MissingKeyError,
+    #[error("meal planner error")]
+    MealPlannerError(#[source] MealPlannerError),
     #[error("file error")]
     FileError(#[from] std::io::Error),
+    #[error("json error")]
+    JsonError(#[from] serde_json::Error),
+    #[error("input error")]
+    InputError(#[from] InputError),
+    #[error("argument error: {0}")]
+    ArgError(String),
 }
 
-async fn prepare(pool: &SqlitePool) -> Result<(), PrepareError> {
-    let key = env::var("TASTY_API_KEY");
-    let string_key: String;
+impl From<sqlx::Error> for PrepareError {
+    fn from(e: sqlx::Error) -> Self {
+        if is_locked_error(&e) {
+            PrepareError::DatabaseLockedError
+        } else {
+            PrepareError::SqlError(e)
+        }
+    }
+}
 
-    match key {
-        Ok(s) => {
-            string_key = s;
+impl From<MealPlannerError> for PrepareError {
+    fn from(e: MealPlannerError) -> Self {
+        match e {
+            MealPlannerError::Sql(e) => e.into(),
+            e => PrepareError::MealPlannerError(e),
+        }
+    }
+}
+
+struct PrepareOptions {
+    format: OutputFormat,
+    dry_run: bool,
+    filter: RecipeFilter,
+    prices_path: Option<PathBuf>,
+    // Overrides `output_dir` from the config
+    output_dir: Option<PathBuf>,
+    quiet: bool,
+    breakdown: bool,
+    // Adds each recipe's yield next to its link
+    include_yields: bool,
+    // Writes out each recipe's steps under its link
+    include_instructions: bool,
+    // Skips the recipe count prompt when set
+    num_recipes: Option<i64>,
+    // Skips the servings prompt when set
+    servings: Option<i64>,
+    strategy: Strategy,
+    seed: Option<u64>,
+    unit_system: UnitSystem,
+    quantity_style: QuantityStyle,
+    no_open: bool,
+    merge_synonyms: bool,
+    // Asks keep or skip for each recipe before anything is written
+    interactive: bool,
+    // HTML plans are always a single page, so this only applies to the other formats
+    files: Files,
+    // Replaces the day's files instead of adding another timestamped block to them
+    overwrite: bool,
+    // Rounds shopping list amounts to what can be measured in a kitchen
+    round: bool,
+    // Saves the raw API responses in the working directory
+    debug_api: bool,
+    // Set from `--profile` once the database is open
+    profile_id: i64,
+}
+
+const INTERACTIVE_CANDIDATE_FACTOR: i64 = 3;
+
+const VALUE_FLAGS: [&str; 19] = [
+    "--format",
+    "--max-minutes",
+    "--blacklist",
+    "--pin",
+    "--prices",
+    "--set-offset",
+    "--num-recipes",
+    "--strategy",
+    "--seed",
+    "--output-dir",
+    "--units",
+    "--max-calories",
+    "--profile",
+    "--since",
+    "--diet",
+    "--servings",
+    "--top-k",
+    "--min-ingredients",
+    "--cooldown-days",
+];
+
+// Arguments that aren't flags or flag values, starting with the subcommand
+fn positionals(args: &[String]) -> Vec<&str> {
+    let mut positionals = Vec::new();
+    let mut iter = args.iter().skip(1);
+
+    while let Some(arg) = iter.next() {
+        if VALUE_FLAGS.contains(&arg.as_str()) {
+            iter.next();
+        } else if !arg.starts_with("--") {
+            positionals.push(arg.as_str());
+        }
+    }
+
+    positionals
+}
+
+fn subcommand(args: &[String]) -> Option<&str> {
+    positionals(args).first().copied()
+}
+
+// What a run does. Without a subcommand, the stored mode picks between preparing and reviewing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Command {
+    Auto,
+    Prepare,
+    Review,
+    Stats,
+    Preferences,
+    Reset,
+    Export,
+    Import,
+    Recompute,
+    Search,
+    History,
+    Undo,
+    Config,
+    Open,
+}
+
+impl FromStr for Command {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "prepare" => Ok(Command::Prepare),
+            "review" => Ok(Command::Review),
+            "stats" => Ok(Command::Stats),
+            "preferences" => Ok(Command::Preferences),
+            "reset" => Ok(Command::Reset),
+            "export" => Ok(Command::Export),
+            "import" => Ok(Command::Import),
+            "recompute" => Ok(Command::Recompute),
+            "search" => Ok(Command::Search),
+            "history" => Ok(Command::History),
+            "undo" => Ok(Command::Undo),
+            "config" => Ok(Command::Config),
+            "open" => Ok(Command::Open),
+            _ => Err(format!("unknown command `{}`", s)),
+        }
+    }
+}
+
+fn command(args: &[String]) -> Result<Command, PrepareError> {
+    match subcommand(args) {
+        Some(name) => name.parse().map_err(|e: String| {
+            eprintln!("{}, expected one of prepare, review, stats, preferences, reset, export, import, recompute, search, history, undo, config, or open.", e);
+            PrepareError::ArgError(e)
+        }),
+        None => Ok(Command::Auto),
+    }
+}
+
+fn parse_flag<T>(args: &[String], flag: &str) -> Result<Option<T>, PrepareError>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    match flag_value(args, flag) {
+        Some(s) => s.parse().map(Some).map_err(|e: T::Err| {
+            eprintln!("Invalid value for {}: {}", flag, e);
+            PrepareError::ArgError(e.to_string())
+        }),
+        None => Ok(None),
+    }
+}
+
+// Every value of a flag that can be given more than once
+fn parse_flags<T>(args: &[String], flag: &str) -> Result<Vec<T>, PrepareError>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    args.iter()
+        .zip(args.iter().skip(1))
+        .filter(|(arg, _)| *arg == flag)
+        .map(|(_, value)| {
+            value.parse().map_err(|e: T::Err| {
+                eprintln!("Invalid value for {}: {}", flag, e);
+                PrepareError::ArgError(e.to_string())
+            })
+        })
+        .collect()
+}
+
+fn has_flag(args: &[String], flag: &str) -> bool {
+    args.iter().any(|arg| arg == flag)
+}
+
+fn flag_value<'a>(args: &'a [String], flag: &str) -> Option<&'a str> {
+    args.iter()
+        .position(|arg| arg == flag)
+        .and_then(|i| args.get(i + 1))
+        .map(String::as_str)
+}
+
+// Which of the plan's files `prepare` writes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Files {
+    Both,
+    ListOnly,
+    RecipesOnly,
+}
+
+// A file written by `prepare`
+struct Output {
+    path: PathBuf,
+    content: String,
+    append: bool,
+}
+
+// Lines flagging the ingredients in `avoid` that the recipe uses, with a substitute when one is known
+fn substitution_notes(recipe: &Recipe, avoid: &[String]) -> Vec<String> {
+    if avoid.is_empty() {
+        return Vec::new();
+    }
+
+    let components = get_components(std::slice::from_ref(recipe));
+    let avoided = pantry_ingredient_ids(avoid, &components);
+
+    suggest_substitutions(&components, &avoided)
+        .iter()
+        .map(ToString::to_string)
+        .collect()
+}
+
+const NO_RECIPES_MESSAGE: &str =
+    "No recipes available at this offset; try resetting the offset with --reset-offset.";
+
+// Stops the spinner, explaining what went wrong when the search failed. Running out of recipes
+// isn't a failed search.
+fn finish_search<T>(
+    result: Result<T, MealPlannerError>,
+    spinner: Option<&mut Spinner>,
+) -> Result<T, PrepareError> {
+    let message = match &result {
+        Ok(_) | Err(MealPlannerError::NoRecipes) => None,
+        Err(MealPlannerError::Api(ApiError::Timeout(_))) => Some("Request timed out"),
+        Err(MealPlannerError::Api(ApiError::Unauthorized)) => {
+            Some("The Tasty API key was rejected, check TASTY_API_KEY")
         }
-        Err(e) => {
-            eprintln!("Please set the TASTY_API_KEY environment variable to your Tasty API key and try again.\nConsider using a .env file: https://hexdocs.pm/dotenvy/0.5.0/dotenv-file-format.html");
-            return Err(e.into());
+        Err(MealPlannerError::Api(ApiError::RateLimited { .. })) => {
+            Some("Too many requests to the Tasty API, try again later")
         }
+        Err(_) => Some("Failed to search recipes"),
     };
 
-    let n_recipes: i64 = validation_input(Some("How many recipes do you want? "), None);
+    match (spinner, message) {
+        (Some(spinner), Some(message)) => spinner.fail(message),
+        (None, Some(message)) => eprintln!("{}", message),
+        (Some(spinner), None) => spinner.success("Done!"),
+        (None, None) => {}
+    }
 
-    let mut spinner = Spinner::new(spinners::Arc, "Searching recipes...", Color::Blue);
-    let all_recipes = remove_duplicate_recipes(
-        get_recipes_list(get_offset(pool).await?, 200, &string_key).await?,
-        pool,
-    )
-    .await?;
-    spinner.success("Done!");
+    result.map_err(Into::into)
+}
 
-    let recipes = get_matching_recipes(all_recipes, n_recipes, pool).await?;
-    let components = get_components(&recipes);
-    let shopping_list = make_shopping_list(components)?;
+// Without a pool nothing is stored, so the plan is only printed
+async fn prepare(
+    pool: Option<&SqlitePool>,
+    options: PrepareOptions,
+    config: Config,
+) -> Result<(), PrepareError> {
+    let string_key = match resolve_api_key_from(config.tasty_api_key.as_deref()) {
+        Ok(key) => key,
+        Err(KeyError::Missing) => {
+            let config_location = config_path()
+                .map(|p| p.display().to_string())
+                .unwrap_or("the config file".to_owned());
+            eprintln!("Please set the TASTY_API_KEY environment variable to your Tasty API key and try again.\nConsider using a .env file: https://hexdocs.pm/dotenvy/0.5.0/dotenv-file-format.html\nYou can also set `tasty_api_key` in {}", config_location);
+            return Err(PrepareError::MissingKeyError);
+        }
+    };
+
+    let n_recipes: i64 = match options.num_recipes {
+        Some(n) => n,
+        None => validation_input(Some("How many recipes do you want? "), None)?,
+    };
+    let servings: i64 = match options.servings {
+        Some(n) => n,
+        None => validation_input(Some("How many servings should each recipe make? "), None)?,
+    };
+
+    // The spinner redraws the line it's on, which garbles output that isn't going to a terminal
+    let mut spinner =
+        (!options.quiet).then(|| Spinner::new(spinners::Arc, "Searching recipes...", Color::Blue));
+    let mut api = TastyApi::new(string_key);
+    if options.debug_api {
+        eprintln!("Saving raw API responses as tasty-response-<offset>.json.");
+        api = api.with_response_dump(PathBuf::new());
+    }
+    let plan_options = PlanOptions {
+        servings: Some(servings),
+        filter: options.filter,
+        pantry: config.pantry,
+        strategy: options.strategy,
+        merge_synonyms: options.merge_synonyms,
+        seed: options.seed,
+        unit_system: options.unit_system,
+        rounding: options.round.then(|| {
+            config
+                .rounding_tolerance
+                .unwrap_or(DEFAULT_ROUNDING_TOLERANCE)
+        }),
+        page_size: config.page_size.unwrap_or(MAX_PAGE_SIZE),
+        profile_id: options.profile_id,
+        ..Default::default()
+    };
+    // Interactive runs rank extra candidates, so skipped recipes have something to be replaced
+    // with. Other runs are planned in one go.
+    let Plan {
+        recipes,
+        shopping_list,
+        offset_consumed,
+    } = if options.interactive {
+        let needed = n_recipes * INTERACTIVE_CANDIDATE_FACTOR;
+        let candidates = finish_search(
+            rank_candidates(pool, &api, needed, &plan_options).await,
+            spinner.as_mut(),
+        )?;
+        if candidates.recipes.is_empty() {
+            eprintln!("{}", NO_RECIPES_MESSAGE);
+            return Ok(());
+        }
 
-    let now = Local::now();
+        let chosen = choose_recipes(candidates.recipes, n_recipes as usize)?;
+        if chosen.is_empty() {
+            eprintln!("No recipes were kept, so no plan was made.");
+            return Ok(());
+        }
+
+        build_plan(chosen, candidates.pages_fetched, n_recipes, &plan_options)?
+    } else {
+        let plan = match pool {
+            Some(pool) => generate_plan(pool, &api, n_recipes, plan_options).await,
+            None => generate_stateless_plan(&api, n_recipes, plan_options).await,
+        };
+
+        match finish_search(plan, spinner.as_mut()) {
+            Err(PrepareError::MealPlannerError(MealPlannerError::NoRecipes)) => {
+                eprintln!("{}", NO_RECIPES_MESSAGE);
+                return Ok(());
+            }
+            plan => plan?,
+        }
+    };
+
+    let output_dir = options.output_dir.or(config.output_dir).unwrap_or_default();
+    let now = Local::now().fixed_offset();
+    let now = match config.utc_offset.as_deref().map(str::parse::<FixedOffset>) {
+        Some(Ok(offset)) => now.with_timezone(&offset),
+        Some(Err(e)) => {
+            eprintln!("Ignoring utc_offset from the config: {}", e);
+            now
+        }
+        None => now,
+    };
     let today = now.date_naive();
-    let time = now.format("%I:%M %P").to_string();
+    let time = format_plan_timestamp(
+        now,
+        config.time_format.as_deref().unwrap_or(DEFAULT_TIME_FORMAT),
+    );
 
-    // Shopping List
-    let shopping_list_file_path = format!("shopping-list-{}.txt", today);
-    {
-        let mut shopping_list_file = OpenOptions::new()
-            .append(true)
-            .create(true)
-            .open(&shopping_list_file_path)
-            .await?;
-        let shopping_list_content = format!(
-            "{}\n{}\n{}\n\n",
-            time,
-            "-".repeat(time.chars().count()),
-            shopping_list
+    let outputs = if options.format == OutputFormat::Html {
+        // Both halves go in one page, which can't be appended to
+        vec![Output {
+            path: output_dir.join(format!("meal-plan-{}.html", today)),
+            content: render_html(
+                &shopping_list,
+                &recipes,
+                options.include_yields,
+                options.include_instructions,
+                &recipes
+                    .iter()
+                    .map(|r| substitution_notes(r, &config.avoid))
+                    .collect::<Vec<_>>(),
+            ),
+            append: false,
+        }]
+    } else {
+        let shopping_list_content = match options.format {
+            OutputFormat::Text => format!(
+                "{}\n{}\n{}\n\n",
+                time,
+                "-".repeat(time.chars().count()),
+                shopping_list.format_with(options.quantity_style)
+            ),
+            OutputFormat::Json => shopping_list_to_json(&shopping_list)?,
+            OutputFormat::Markdown => format!(
+                "## {}\n\n{}\n\n",
+                time,
+                shopping_list_to_markdown(&shopping_list, options.quantity_style)
+            ),
+            OutputFormat::Csv => shopping_list_to_csv(&shopping_list),
+            OutputFormat::Tasks => shopping_list_to_tasks(&shopping_list, options.quantity_style),
+            OutputFormat::Html => unreachable!("HTML plans are written as a single page"),
+        };
+        let recipes_content = match options.format {
+            OutputFormat::Markdown => {
+                format!(
+                    "## {}\n\n{}\n\n",
+                    time,
+                    recipes
+                        .iter()
+                        .map(|r| {
+                            let mut lines = vec![recipes_to_markdown(
+                                std::slice::from_ref(r),
+                                options.include_yields,
+                                options.include_instructions,
+                            )];
+                            lines.extend(
+                                substitution_notes(r, &config.avoid)
+                                    .into_iter()
+                                    .map(|note| format!("    - {}", note)),
+                            );
+                            lines.join("\n")
+                        })
+                        .collect::<Vec<_>>()
+                        .join("\n")
+                )
+            }
+            _ => format!(
+                "{}\n{}\n{}\n\n",
+                time,
+                "-".repeat(time.chars().count()),
+                recipes
+                    .iter()
+                    .map(|r| {
+                        let mut parts = vec![recipe_line(r, options.include_yields)];
+                        if options.breakdown {
+                            parts.push(recipe_ingredient_breakdown(r));
+                        }
+                        if options.include_instructions && !r.instructions.is_empty() {
+                            parts.push(recipe_instructions(r));
+                        }
+                        parts.extend(substitution_notes(r, &config.avoid));
+
+                        // Recipes with more than a link get a blank line after them
+                        if parts.len() > 1 {
+                            format!("{}\n", parts.join("\n"))
+                        } else {
+                            parts.remove(0)
+                        }
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            ),
+        };
+
+        // Shopping List. A task list shares the text extension, so it gets its own name to keep
+        // it from overwriting the day's text list.
+        let shopping_list_name = match options.format {
+            OutputFormat::Tasks => "shopping-tasks",
+            _ => "shopping-list",
+        };
+        let shopping_list_path = format!(
+            "{}-{}.{}",
+            shopping_list_name,
+            today,
+            options.format.extension()
         );
-        shopping_list_file
-            .write_all(shopping_list_content.as_bytes())
-            .await?;
+        // JSON and CSV documents can't be appended to, and a task list should only hold this
+        // plan's items, so they are rewritten on every run
+        let append = !options.overwrite
+            && matches!(options.format, OutputFormat::Text | OutputFormat::Markdown);
+
+        // Recipes
+        let recipes_extension = match options.format {
+            OutputFormat::Markdown => "md",
+            _ => "txt",
+        };
+
+        let mut outputs = Vec::new();
+
+        if options.files != Files::RecipesOnly {
+            outputs.push(Output {
+                path: output_dir.join(shopping_list_path),
+                content: shopping_list_content,
+                append,
+            });
+        }
+
+        if options.files != Files::ListOnly {
+            outputs.push(Output {
+                path: output_dir.join(format!("recipes-{}.{}", today, recipes_extension)),
+                content: recipes_content,
+                append: !options.overwrite,
+            });
+        }
+
+        outputs
+    };
+
+    let mut summary = shopping_list.summary(recipes.len()).to_string();
+
+    if let Some(path) = &options.prices_path {
+        match load_prices(path) {
+            Ok(prices) => {
+                let estimate = estimate_cost(&shopping_list, &prices);
+                summary += &format!(", estimated cost {:.2}", estimate.total);
+
+                if estimate.unpriced > 0 {
+                    summary += &format!(" ({} ingredients without a price)", estimate.unpriced);
+                }
+            }
+            Err(e) => eprintln!("Skipping the cost estimate, {}.", e),
+        }
+    }
+
+    for warning in &shopping_list.warnings {
+        eprintln!("Warning: {}.", warning);
+    }
+
+    let Some(pool) = pool.filter(|_| !options.dry_run) else {
+        for output in &outputs {
+            print!("{}", output.content);
+        }
+        println!("{}", summary);
+        return Ok(());
+    };
+
+    // Saved first, so a failed save doesn't leave files behind for a plan that was never made
+    save_prepare_run(
+        &recipes,
+        offset_consumed,
+        today,
+        &shopping_list.format_with(options.quantity_style),
+        options.profile_id,
+        pool,
+    )
+    .await?;
 
-        shopping_list_file.shutdown().await?;
+    if !output_dir.as_os_str().is_empty() {
+        tokio::fs::create_dir_all(&output_dir).await?;
     }
 
-    // Recipes
-    let recipes_file_path = format!("recipes-{}.txt", today);
-    {
-        let mut recipes_file = OpenOptions::new()
-            .append(true)
+    for output in &outputs {
+        let mut file = OpenOptions::new()
+            .write(true)
+            .append(output.append)
+            .truncate(!output.append)
             .create(true)
-            .open(&recipes_file_path)
+            .open(&output.path)
             .await?;
-        let recipes_content = format!(
-            "{}\n{}\n{}\n\n",
-            time,
-            "-".repeat(time.chars().count()),
-            recipes
-                .iter()
-                .map(|r| format!("https://tasty.co/recipe/{}", r.slug))
-                .collect::<Vec<_>>()
-                .join("\n")
+        file.write_all(output.content.as_bytes()).await?;
+        file.shutdown().await?;
+    }
+
+    if !options.no_open {
+        // The files are already written, so not being able to show them isn't fatal
+        for output in outputs {
+            if let Err(e) = open_file(&output.path) {
+                eprintln!("Could not open {}: {}", output.path.display(), e);
+            }
+        }
+    }
+
+    println!("{}", summary);
+
+    Ok(())
+}
+
+async fn open_recent(output_dir: &Path) -> Result<(), PrepareError> {
+    let dir = if output_dir.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        output_dir
+    };
+
+    let mut file_names = Vec::new();
+    if let Ok(mut entries) = tokio::fs::read_dir(dir).await {
+        while let Some(entry) = entries.next_entry().await? {
+            if let Some(name) = entry.file_name().to_str() {
+                file_names.push(name.to_owned());
+            }
+        }
+    }
+
+    let recent = most_recent_outputs(&file_names);
+    if recent.is_empty() {
+        eprintln!(
+            "No shopping lists or recipe files were found in {}.",
+            dir.display()
         );
-        recipes_file.write_all(recipes_content.as_bytes()).await?;
-        recipes_file.shutdown().await?;
+        return Ok(());
+    }
+
+    for name in recent {
+        let path = output_dir.join(name);
+        if let Err(e) = open_file(&path) {
+            eprintln!("Could not open {}: {}", path.display(), e);
+        }
     }
 
-    open_file(shopping_list_file_path)?;
-    open_file(recipes_file_path)?;
+    Ok(())
+}
 
-    for recipe in recipes {
-        store_recipe(&recipe, pool).await?;
-        store_previous_recipe(&recipe, pool).await?;
+async fn review(
+    pool: &SqlitePool,
+    profile_id: i64,
+    granular: bool,
+    by_tag: bool,
+) -> Result<(), PrepareError> {
+    if by_tag {
+        review_by_tag(pool, profile_id).await?;
     }
 
-    increment_offset(n_recipes, pool).await?;
-    set_mode(Mode::Review, pool).await?;
+    let previous_recipes: Vec<database::Recipe> =
+        get_unrated_previous_recipes(profile_id, pool).await?;
+
+    // Only unrated recipes are left after an interrupted review, so the count covers what remains
+    let total = previous_recipes.len();
+    let ids: Vec<i64> = previous_recipes.iter().map(|recipe| recipe.id).collect();
+    let mut recipe_tags = get_tags_for_recipes(&ids, profile_id, pool).await?;
+
+    for (i, recipe) in previous_recipes.into_iter().enumerate() {
+        let rating: Rating = validation_input(
+            Some(&review_prompt(i + 1, total, &recipe.name)),
+            Some("Please enter dislike (-1), none (0), like (1), or love (2)."),
+        )?;
+
+        let mut tag_ratings: Vec<(i64, Rating)> = Vec::new();
+
+        for tag in recipe_tags.remove(&recipe.id).unwrap_or_default() {
+            let tag_rating = if granular && rating != Rating::None {
+                tag_rating(&tag, rating)?
+            } else {
+                rating
+            };
+
+            tag_ratings.push((tag.id, tag_rating));
+        }
+
+        // Saved one recipe at a time, so quitting partway keeps the ratings given so far
+        apply_recipe_rating(recipe.id, rating, &tag_ratings, profile_id, pool).await?;
+    }
+
+    delete_previous_recipes(profile_id, pool).await?;
+    set_mode(Mode::Prepare, profile_id, pool).await?;
 
     Ok(())
 }
 
-async fn review(pool: &SqlitePool) -> Result<(), sqlx::Error> {
-    let previous_recipes: Vec<database::Recipe> = get_previous_recipes(&pool).await?;
+fn review_prompt(position: usize, total: usize, name: &str) -> String {
+    format!(
+        "({}/{}) How did you like {} (dislike -1, none 0, like 1, or love 2)? ",
+        position, total, name
+    )
+}
+
+// Lets the user rate every unrated recipe sharing a tag at once; whatever is
+// left over is reviewed one recipe at a time afterwards
+async fn review_by_tag(pool: &SqlitePool, profile_id: i64) -> Result<(), PrepareError> {
+    let mut remaining: Vec<i64> = get_unrated_previous_recipes(profile_id, pool)
+        .await?
+        .into_iter()
+        .map(|recipe| recipe.id)
+        .collect();
+
+    let recipe_tags = get_tags_for_recipes(&remaining, profile_id, pool).await?;
+
+    while !remaining.is_empty() {
+        let mut counts: HashMap<i64, (String, usize)> = HashMap::new();
+        for tag in remaining.iter().flat_map(|id| &recipe_tags[id]) {
+            counts
+                .entry(tag.id)
+                .or_insert_with(|| (tag_label(tag), 0))
+                .1 += 1;
+        }
+
+        let mut tags: Vec<(i64, String, usize)> = counts
+            .into_iter()
+            .map(|(id, (label, count))| (id, label, count))
+            .collect();
+        tags.sort_by(|a, b| b.2.cmp(&a.2).then_with(|| a.1.cmp(&b.1)));
+
+        if tags.is_empty() {
+            break;
+        }
+
+        println!("Tags in this batch:");
+        for (i, (_, label, count)) in tags.iter().enumerate() {
+            println!("  {}. {} ({} recipes)", i + 1, label, count);
+        }
+
+        let input: String = validation_input(
+            Some("Enter a tag number to rate its recipes together, or press enter to rate individually: "),
+            None,
+        )?;
+
+        if input.is_empty() {
+            break;
+        }
+
+        let Some((tag_id, label, _)) = input
+            .parse::<usize>()
+            .ok()
+            .and_then(|i| i.checked_sub(1))
+            .and_then(|i| tags.get(i))
+        else {
+            eprintln!("Please enter a number between 1 and {}.", tags.len());
+            continue;
+        };
 
-    for recipe in previous_recipes {
         let rating: Rating = validation_input(
             Some(&format!(
-                "How did you like {} (dislike, none, like, or love)? ",
-                recipe.name
+                "How did you like recipes tagged {} (dislike -1, none 0, like 1, or love 2)? ",
+                label
             )),
-            Some("Please enter a dislike, none, like, or love."),
-        );
+            Some("Please enter dislike (-1), none (0), like (1), or love (2)."),
+        )?;
+
+        let group = recipes_with_tag(*tag_id, &remaining, pool).await?;
+        for recipe_id in &group {
+            let tag_ratings: Vec<(i64, Rating)> = recipe_tags[recipe_id]
+                .iter()
+                .map(|tag| (tag.id, rating))
+                .collect();
+
+            apply_recipe_rating(*recipe_id, rating, &tag_ratings, profile_id, pool).await?;
+        }
+
+        remaining.retain(|id| !group.contains(id));
+    }
+
+    Ok(())
+}
+
+// Tags stored before names were recorded only have an id
+fn tag_label(tag: &database::models::Tag) -> String {
+    if tag.name.is_empty() {
+        tag.id.to_string()
+    } else {
+        tag.name.clone()
+    }
+}
+
+// Lets a single tag override the rating given to the whole recipe, keeping it on empty input
+fn tag_rating(tag: &database::models::Tag, recipe_rating: Rating) -> Result<Rating, PrepareError> {
+    let label = tag_label(tag);
 
-        for tag in get_recipe_tags(recipe.id, pool).await? {
-            update_tag_likes(tag.id, rating.value(), pool).await?;
+    loop {
+        let input: String = validation_input(
+            Some(&format!(
+                "  How did you like {} (enter to keep {})? ",
+                label, recipe_rating
+            )),
+            None,
+        )?;
+
+        if input.is_empty() {
+            return Ok(recipe_rating);
+        }
+
+        match input.parse() {
+            Ok(rating) => return Ok(rating),
+            Err(e) => eprintln!("{}", e),
         }
     }
+}
+
+fn print_stats(stats: &Stats) {
+    println!("Rated recipes: {}", stats.rated_recipes);
+    for (rating, count) in &stats.rating_counts {
+        println!("  {:<8} {}", rating.to_string(), count);
+    }
+
+    let tag_list = |tags: &[database::models::Tag]| {
+        tags.iter()
+            .map(|tag| format!("{} ({})", tag_label(tag), tag.likes))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+
+    if !stats.top_tags.is_empty() {
+        println!("Most liked tags: {}", tag_list(&stats.top_tags));
+    }
+    if !stats.bottom_tags.is_empty() {
+        println!("Most disliked tags: {}", tag_list(&stats.bottom_tags));
+    }
+}
 
-    delete_previous_recipes(pool).await?;
-    set_mode(Mode::Prepare, pool).await?;
+async fn preferences(pool: &SqlitePool, profile_id: i64) -> Result<(), PrepareError> {
+    loop {
+        let tags = get_all_tags(profile_id, pool).await?;
+
+        if tags.is_empty() {
+            println!("No tag preferences have been learned yet.");
+            return Ok(());
+        }
+
+        println!("{:>10}  {:>5}  name", "tag", "likes");
+        for tag in &tags {
+            println!("{:>10}  {:>5}  {}", tag.id, tag.likes, tag.name);
+        }
+
+        let input: String =
+            validation_input(Some("Enter a tag id to change, or done to finish: "), None)?;
+
+        if input.eq_ignore_ascii_case("done") {
+            return Ok(());
+        }
+
+        let tag_id: i64 = match input.parse() {
+            Ok(id) if tags.iter().any(|tag| tag.id == id) => id,
+            _ => {
+                eprintln!("Please enter one of the tag ids above.");
+                continue;
+            }
+        };
+
+        let likes: i64 = validation_input(
+            Some(&format!(
+                "New likes value for tag {} (0 to reset): ",
+                tag_id
+            )),
+            Some("Please enter a whole number."),
+        )?;
+
+        set_tag_likes(tag_id, likes, profile_id, pool).await?;
+    }
+}
+
+// `config` shows the stored defaults, `config set <key> <value>` changes one, and
+// `config unset <key>` goes back to the built-in default
+async fn settings_command(
+    args: &[&str],
+    profile_id: i64,
+    pool: &SqlitePool,
+) -> Result<(), PrepareError> {
+    const KEYS: &str = "format, servings, or diet";
+
+    let (key, value) = match args {
+        [] => {
+            let settings = get_settings(profile_id, pool).await?;
+            let show = |value: Option<String>| value.unwrap_or("not set".to_owned());
+
+            println!("format:   {}", show(settings.default_format));
+            println!(
+                "servings: {}",
+                show(settings.default_servings.map(|n| n.to_string()))
+            );
+            println!("diet:     {}", show(settings.default_diet));
+
+            return Ok(());
+        }
+        ["set", key, value] => (*key, Some(*value)),
+        ["unset", key] => (*key, None),
+        _ => {
+            eprintln!("Please use `config`, `config set <key> <value>`, or `config unset <key>`.");
+            return Err(PrepareError::ArgError("invalid config command".to_owned()));
+        }
+    };
+
+    // Checked here so that a bad value never reaches the database
+    let invalid = |e: String| {
+        eprintln!("Invalid value for {}: {}", key, e);
+        PrepareError::ArgError(e)
+    };
+
+    match key {
+        "format" => {
+            if let Some(value) = value {
+                value
+                    .parse::<OutputFormat>()
+                    .map_err(|e| invalid(e.to_owned()))?;
+            }
+            set_default_format(value, profile_id, pool).await?;
+        }
+        "servings" => {
+            let servings = match value.map(str::parse::<i64>) {
+                Some(Ok(n)) if n >= 1 => Some(n),
+                Some(_) => return Err(invalid("expected a whole number of at least 1".to_owned())),
+                None => None,
+            };
+            set_default_servings(servings, profile_id, pool).await?;
+        }
+        "diet" => {
+            if let Some(value) = value {
+                value.parse::<Diet>().map_err(invalid)?;
+            }
+            set_default_diet(value, profile_id, pool).await?;
+        }
+        _ => {
+            eprintln!("Unknown setting `{}`, expected {}.", key, KEYS);
+            return Err(PrepareError::ArgError("unknown setting".to_owned()));
+        }
+    }
+
+    match value {
+        Some(value) => println!("The default {} is now {}.", key, value),
+        None => println!("Cleared the default {}.", key),
+    }
+
+    Ok(())
+}
+
+// Flags win over the profile's stored settings, which win over the built-in defaults
+async fn apply_settings(
+    args: &[String],
+    options: &mut PrepareOptions,
+    pool: &SqlitePool,
+) -> Result<(), PrepareError> {
+    let settings = get_settings(options.profile_id, pool).await?;
+
+    if flag_value(args, "--format").is_none() {
+        if let Some(format) = settings.default_format.and_then(|f| f.parse().ok()) {
+            options.format = format;
+        }
+    }
+    if options.servings.is_none() {
+        options.servings = settings.default_servings;
+    }
+    if !has_flag(args, "--diet") {
+        if let Some(diet) = settings.default_diet.and_then(|d| d.parse::<Diet>().ok()) {
+            options.filter.required_tag_ids = diet_tag_ids(&[diet]);
+        }
+    }
 
     Ok(())
 }
@@ -157,35 +1001,329 @@ async fn review(pool: &SqlitePool) -> Result<(), sqlx::Error> {
 #[tokio::main]
 async fn main_() -> Result<(), PrepareError> {
     dotenvy::dotenv().ok();
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn")).init();
+
+    let args: Vec<String> = env::args().collect();
+    let config = load_config();
+    let mut options = PrepareOptions {
+        format: parse_flag(&args, "--format")?.unwrap_or(OutputFormat::Text),
+        dry_run: has_flag(&args, "--dry-run"),
+        filter: RecipeFilter {
+            required_tag_ids: diet_tag_ids(&parse_flags::<Diet>(&args, "--diet")?),
+            max_minutes: parse_flag(&args, "--max-minutes")?,
+            include_untimed: !has_flag(&args, "--exclude-untimed"),
+            max_calories: parse_flag(&args, "--max-calories")?,
+            include_uncounted: !has_flag(&args, "--exclude-uncounted"),
+            min_created: parse_flag(&args, "--since")?,
+            include_undated: !has_flag(&args, "--exclude-undated"),
+            min_ingredients: parse_flag(&args, "--min-ingredients")?.unwrap_or(1),
+            cooldown_days: parse_flag(&args, "--cooldown-days")?,
+        },
+        no_open: has_flag(&args, "--no-open"),
+        merge_synonyms: has_flag(&args, "--merge-synonyms"),
+        interactive: has_flag(&args, "--interactive"),
+        overwrite: has_flag(&args, "--overwrite"),
+        round: has_flag(&args, "--round"),
+        debug_api: has_flag(&args, "--debug-api"),
+        files: match (
+            has_flag(&args, "--list-only"),
+            has_flag(&args, "--recipes-only"),
+        ) {
+            (true, true) => {
+                eprintln!("--list-only and --recipes-only can't be used together");
+                return Err(PrepareError::ArgError(
+                    "conflicting output flags".to_owned(),
+                ));
+            }
+            (true, false) => Files::ListOnly,
+            (false, true) => Files::RecipesOnly,
+            (false, false) => Files::Both,
+        },
+        prices_path: flag_value(&args, "--prices").map(PathBuf::from),
+        output_dir: flag_value(&args, "--output-dir").map(PathBuf::from),
+        quiet: has_flag(&args, "--quiet"),
+        breakdown: has_flag(&args, "--breakdown"),
+        include_yields: has_flag(&args, "--include-yields"),
+        include_instructions: has_flag(&args, "--include-instructions"),
+        num_recipes: parse_flag(&args, "--num-recipes")?,
+        servings: parse_flag(&args, "--servings")?,
+        strategy: match (
+            parse_flag::<Strategy>(&args, "--strategy")?,
+            parse_flag::<usize>(&args, "--top-k")?,
+        ) {
+            (Some(_), Some(_)) => {
+                eprintln!("--strategy and --top-k can't be used together");
+                return Err(PrepareError::ArgError(
+                    "conflicting strategy flags".to_owned(),
+                ));
+            }
+            (_, Some(0)) => {
+                eprintln!("Invalid value for --top-k: at least one recipe is needed");
+                return Err(PrepareError::ArgError("empty top-k pool".to_owned()));
+            }
+            (_, Some(k)) => Strategy::ShuffledTop(k),
+            (strategy, None) => strategy.unwrap_or_default(),
+        },
+        seed: parse_flag(&args, "--seed")?,
+        unit_system: parse_flag(&args, "--units")?.unwrap_or_default(),
+        quantity_style: if has_flag(&args, "--fractions") {
+            QuantityStyle::Fraction
+        } else {
+            QuantityStyle::Decimal
+        },
+        profile_id: DEFAULT_PROFILE_ID,
+    };
+
+    if matches!(options.servings, Some(n) if n < 1) {
+        eprintln!("Invalid value for --servings: each recipe needs at least one serving");
+        return Err(PrepareError::ArgError("non-positive servings".to_owned()));
+    }
+
+    if matches!(options.filter.cooldown_days, Some(n) if n < 0) {
+        eprintln!("Invalid value for --cooldown-days: the cooldown can't be negative");
+        return Err(PrepareError::ArgError("negative cooldown".to_owned()));
+    }
+
+    if matches!(options.num_recipes, Some(n) if n < 1) {
+        eprintln!("Invalid value for --num-recipes: at least one recipe is needed");
+        return Err(PrepareError::ArgError(
+            "non-positive recipe count".to_owned(),
+        ));
+    }
+
+    let command = command(&args)?;
+
+    // Only looks at files already written, so the database isn't needed
+    if command == Command::Open {
+        let output_dir = options.output_dir.or(config.output_dir).unwrap_or_default();
+        return open_recent(&output_dir).await;
+    }
+
+    // One-off plans that leave no database behind
+    if has_flag(&args, "--stateless") {
+        if options.filter.cooldown_days.is_some() {
+            eprintln!(
+                "--cooldown-days needs the planning history, so it can't be used with --stateless."
+            );
+            return Err(PrepareError::ArgError(
+                "cooldown without a database".to_owned(),
+            ));
+        }
+
+        return prepare(None, options, config).await;
+    }
 
-    let pool = SqlitePoolOptions::new()
-        .max_connections(5)
-        .connect("sqlite://database.db?mode=rwc")
-        .await?;
+    let db_path = resolve_db_path();
+    let max_connections = config.max_connections.unwrap_or(DEFAULT_MAX_CONNECTIONS);
+
+    // Dry runs only read the database, and plan without it when there's nothing to read yet
+    if options.dry_run {
+        let Some(pool) = open_existing(&db_path, max_connections).await? else {
+            return prepare(None, options, config).await;
+        };
+
+        if let Some(name) = flag_value(&args, "--profile") {
+            match find_profile(name, &pool).await? {
+                Some(profile_id) => options.profile_id = profile_id,
+                None => return prepare(None, options, config).await,
+            }
+        }
+
+        apply_settings(&args, &mut options, &pool).await?;
+
+        return prepare(Some(&pool), options, config).await;
+    }
+
+    if let Some(parent) = db_path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+
+    let pool = create_pool(
+        &format!("sqlite://{}?mode=rwc", db_path.display()),
+        max_connections,
+    )
+    .await?;
 
     if !tables_exist(&pool).await {
         create_tables(&pool).await?;
         populate_data_table(&pool).await?;
     }
 
-    let mode = get_mode(&pool).await?;
+    run_migrations(&pool).await?;
+
+    // Each profile keeps its own likes, offset, and recipes awaiting review
+    let profile_id = match flag_value(&args, "--profile") {
+        Some(name) => get_or_create_profile(name, &pool).await?,
+        None => DEFAULT_PROFILE_ID,
+    };
+    options.profile_id = profile_id;
+    apply_settings(&args, &mut options, &pool).await?;
+
+    if let Some(recipe_id) = parse_flag(&args, "--blacklist")? {
+        blacklist_recipe(recipe_id, profile_id, &pool).await?;
+        println!("Recipe {} will no longer be suggested.", recipe_id);
 
-    if mode == Mode::Prepare {
-        prepare(&pool).await?;
+        return Ok(());
+    }
+
+    let new_offset = if has_flag(&args, "--reset-offset") {
+        Some(0)
     } else {
-        review(&pool).await?;
+        parse_flag::<i64>(&args, "--set-offset")?
+    };
+
+    if let Some(offset) = new_offset {
+        if offset < 0 {
+            eprintln!("Invalid value for --set-offset: the offset can't be negative");
+            return Err(PrepareError::ArgError("negative offset".to_owned()));
+        }
+
+        set_offset(offset, profile_id, &pool).await?;
+        println!("The recipe offset is now {}.", offset);
+
+        return Ok(());
+    }
+
+    if let Some(recipe_id) = parse_flag(&args, "--pin")? {
+        pin_recipe(recipe_id, profile_id, &pool).await?;
+        println!("Recipe {} will be in every plan it fits.", recipe_id);
+
+        return Ok(());
+    }
+
+    let granular = has_flag(&args, "--granular-review");
+    let by_tag = has_flag(&args, "--review-by-tag");
+
+    match command {
+        Command::Preferences => preferences(&pool, profile_id).await?,
+        Command::Export => {
+            let json = serde_json::to_string_pretty(&export_all(&pool).await?)?;
+
+            match positionals(&args).get(1) {
+                Some(path) => {
+                    tokio::fs::write(path, json).await?;
+                    println!("Exported the database to {}.", path);
+                }
+                None => println!("{}", json),
+            }
+        }
+        Command::Import => {
+            let Some(path) = positionals(&args).get(1).map(|p| p.to_string()) else {
+                eprintln!("Please give the file to import, like `import backup.json`.");
+                return Err(PrepareError::ArgError("missing import file".to_owned()));
+            };
+
+            let export: DatabaseExport =
+                serde_json::from_str(&tokio::fs::read_to_string(&path).await?)?;
+            import_all(&export, &pool).await?;
+            println!("Imported the database from {}.", path);
+        }
+        Command::Recompute => {
+            recompute_tag_likes(config.decay_half_life_weeks, profile_id, &pool).await?;
+            println!("Rebuilt tag likes from the rating history.");
+        }
+        Command::Search => {
+            let query = positionals(&args)[1..].join(" ");
+            let recipes = search_recipes(&query, profile_id, &pool).await?;
+
+            if recipes.is_empty() {
+                println!("No stored recipes match \"{}\".", query);
+            }
+
+            for recipe in recipes {
+                match recipe.url() {
+                    Some(url) => println!("{} — {}", recipe.name, url),
+                    None => println!("{}", recipe.name),
+                }
+            }
+        }
+        Command::Reset => {
+            if !has_flag(&args, "--confirm") {
+                eprintln!("This deletes all ratings, recipes, and history. Run `reset --confirm` to go ahead.");
+                return Err(PrepareError::ArgError("reset without --confirm".to_owned()));
+            }
+
+            reset_all(&pool).await?;
+            println!("Cleared the database.");
+        }
+        Command::History => {
+            let history = get_shopping_list_history(profile_id, &pool).await?;
+
+            if history.is_empty() {
+                println!("No shopping lists have been made yet.");
+            }
+
+            for list in history {
+                println!(
+                    "{}\n{}\n{}\n",
+                    list.date,
+                    "-".repeat(list.date.len()),
+                    list.content
+                );
+            }
+        }
+        Command::Stats => {
+            print_stats(&rating_stats(profile_id, &pool).await?);
+        }
+        Command::Undo => {
+            match undo_last_prepare(profile_id, &pool).await? {
+                UndoOutcome::Undone => println!("Undid the last prepare run."),
+                UndoOutcome::NothingToUndo => println!("There is no prepare run to undo."),
+                UndoOutcome::AlreadyRated => println!(
+                    "The last prepare run can't be undone, some of its recipes were already rated and their ratings applied. Finish the review instead."
+                ),
+            }
+        }
+        Command::Config => settings_command(&positionals(&args)[1..], profile_id, &pool).await?,
+        Command::Open => unreachable!("open is handled before the database is opened"),
+        Command::Prepare => prepare(Some(&pool), options, config).await?,
+        Command::Review => review(&pool, profile_id, granular, by_tag).await?,
+        Command::Auto => match get_mode(profile_id, &pool).await? {
+            Mode::Prepare => prepare(Some(&pool), options, config).await?,
+            Mode::Review => review(&pool, profile_id, granular, by_tag).await?,
+        },
     }
 
     Ok(())
 }
 
+// The error followed by each of its causes, like "file error: No such file or directory"
+fn error_chain(e: &dyn std::error::Error) -> String {
+    let mut message = e.to_string();
+    let mut source = e.source();
+
+    while let Some(e) = source {
+        message.push_str(&format!(": {}", e));
+        source = e.source();
+    }
+
+    message
+}
+
 fn main() {
-    main_().ok();
+    let result = main_();
+
+    match &result {
+        Err(e @ PrepareError::DatabaseLockedError) => {
+            eprintln!("The {}, close it and try again.", e)
+        }
+        // These were already explained where they happened
+        Err(PrepareError::ArgError(_) | PrepareError::MissingKeyError) => {}
+        Err(e) => eprintln!("Error: {}", error_chain(e)),
+        Ok(()) => {}
+    }
+
+    // Nobody is around to press enter when running quietly from a script
+    if !has_flag(&env::args().collect::<Vec<_>>(), "--quiet") {
+        let mut lock = io::stdout().lock();
+        write!(lock, "Press enter to exit...").unwrap();
+        lock.flush().ok();
 
-    let mut lock = io::stdout().lock();
-    write!(lock, "Press enter to exit...").unwrap();
-    lock.flush().ok();
+        let mut buf = String::new();
+        std::io::stdin().read_line(&mut buf).ok();
+    }
 
-    let mut buf = String::new();
-    std::io::stdin().read_line(&mut buf).ok();
+    if result.is_err() {
+        std::process::exit(1);
+    }
 }